@@ -0,0 +1,210 @@
+//!
+//! retry timing and health tracking for feeds that fail to update
+//!
+
+use std::time::{Duration, SystemTime};
+
+use super::redact;
+
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// how many times `max_delay` a `Retry-After` from the server may ask us to wait
+pub const RETRY_AFTER_LIMIT: u64 = 4;
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct RetryConfig {
+    /// seconds to wait after the first failure
+    #[serde(default = "default_base_delay")]
+    pub base_delay: u64,
+    /// upper bound in seconds for the exponential delay
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+    /// number of consecutive failures before a feed is considered failing
+    #[serde(default = "default_failing_after")]
+    pub failing_after: u32,
+}
+
+fn default_base_delay() -> u64 {
+    60
+}
+fn default_max_delay() -> u64 {
+    60 * 60
+}
+fn default_failing_after() -> u32 {
+    5
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            failing_after: default_failing_after(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Failing,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FeedHealth {
+    pub state: HealthState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    config: RetryConfig,
+}
+
+impl FeedHealth {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            state: HealthState::Healthy,
+            consecutive_failures: 0,
+            last_success: None,
+            last_error: None,
+            config,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.state = HealthState::Healthy;
+        self.consecutive_failures = 0;
+        self.last_success = Some(SystemTime::now());
+    }
+
+    /// record a failed update and return how long to wait before trying again.
+    /// a `retry_after` sent by the server is honored past `max_delay`, but only up to
+    /// [`RETRY_AFTER_LIMIT`] times it
+    pub fn record_failure(&mut self, error: String, retry_after: Option<Duration>) -> Duration {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_error = Some(redact::text(&error));
+
+        self.state = if self.consecutive_failures >= self.config.failing_after {
+            HealthState::Failing
+        } else {
+            HealthState::Degraded
+        };
+
+        let delay = self.backoff();
+
+        // a server asking for days, or sending a date far in the future, would
+        // otherwise stop the feed for good
+        let limit = Duration::from_secs(self.config.max_delay.saturating_mul(RETRY_AFTER_LIMIT));
+
+        match retry_after {
+            Some(retry_after) => retry_after.min(limit).max(delay),
+            None => delay,
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        delay(
+            self.config.base_delay,
+            self.config.max_delay,
+            self.consecutive_failures,
+        )
+    }
+}
+
+/// exponential delay in seconds for the `attempt`th failure in a row, with "equal
+/// jitter": half of the delay is fixed and the other half is random so that things
+/// failing together do not retry in lockstep
+pub fn delay(base_delay: u64, max_delay: u64, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(32);
+    let delay = base_delay
+        .saturating_mul(1u64 << exponent)
+        .min(max_delay)
+        .max(1);
+
+    let half = delay * 1000 / 2;
+    let jitter = rand::thread_rng().gen_range(0..=half);
+
+    Duration::from_millis(half + jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            base_delay: 10,
+            max_delay: 100,
+            failing_after: 3,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_within_jitter() {
+        for (attempt, full) in [(1, 10), (2, 20), (3, 40), (4, 80)] {
+            for _ in 0..50 {
+                let delay = delay(10, 1000, attempt);
+                assert!(delay >= Duration::from_millis(full * 1000 / 2));
+                assert!(delay <= Duration::from_secs(full));
+            }
+        }
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        for attempt in [8, 40, u32::MAX] {
+            let delay = delay(10, 100, attempt);
+            assert!(delay >= Duration::from_secs(50));
+            assert!(delay <= Duration::from_secs(100));
+        }
+    }
+
+    #[test]
+    fn delay_is_never_zero() {
+        assert!(delay(0, 0, 1) >= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn failures_degrade_then_fail() {
+        let mut health = FeedHealth::new(config());
+
+        health.record_failure("one".to_string(), None);
+        assert_eq!(health.state, HealthState::Degraded);
+        health.record_failure("two".to_string(), None);
+        assert_eq!(health.state, HealthState::Degraded);
+        health.record_failure("three".to_string(), None);
+        assert_eq!(health.state, HealthState::Failing);
+        assert_eq!(health.consecutive_failures, 3);
+        assert_eq!(health.last_error.as_deref(), Some("three"));
+
+        health.record_success();
+        assert_eq!(health.state, HealthState::Healthy);
+        assert_eq!(health.consecutive_failures, 0);
+        assert!(health.last_success.is_some());
+    }
+
+    #[test]
+    fn retry_after_is_honored_past_max_delay() {
+        let mut health = FeedHealth::new(config());
+        let wait = health.record_failure("429".to_string(), Some(Duration::from_secs(300)));
+        assert_eq!(wait, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let mut health = FeedHealth::new(config());
+        let wait = health.record_failure("429".to_string(), Some(Duration::from_secs(86400)));
+        assert_eq!(wait, Duration::from_secs(100 * RETRY_AFTER_LIMIT));
+    }
+
+    #[test]
+    fn short_retry_after_does_not_undercut_backoff() {
+        let mut health = FeedHealth::new(config());
+        let wait = health.record_failure("429".to_string(), Some(Duration::from_secs(1)));
+        assert!(wait >= Duration::from_secs(5));
+    }
+}
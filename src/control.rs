@@ -0,0 +1,175 @@
+//!
+//! handles for reaching running feed tasks from the outside
+//!
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::yaml::TorrentMatch;
+use super::Error;
+
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+type Reply<T> = oneshot::Sender<Result<T, Error>>;
+
+/// requests handled by a feed task between polls
+#[derive(Debug)]
+pub enum FeedCommand {
+    Poll(Reply<()>),
+    ListMatchers(Reply<Vec<TorrentMatch>>),
+    AddMatcher(TorrentMatch, Reply<usize>),
+    UpdateMatcher(usize, TorrentMatch, Reply<()>),
+    RemoveMatcher(usize, Reply<TorrentMatch>),
+    // try a failed download again, by rss item hash
+    Retry(u64, Reply<()>),
+    // the items from the last fetch of the feed
+    Items(Reply<Vec<ItemView>>),
+    // run a matcher that is not saved yet against the last fetch of the feed
+    Preview(TorrentMatch, Reply<Vec<ItemView>>),
+}
+
+/// an item from the last fetch of a feed and what the feed made of it
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemView {
+    pub title: String,
+    pub tags: Vec<String>,
+    pub size: Option<u64>,
+    pub item_hash: u64,
+    // index of the first matcher accepting the item
+    pub matcher: Option<usize>,
+    pub state: ItemState,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemState {
+    Unmatched,
+    // accepted by a matcher but not sent to qbittorrent yet
+    Matched,
+    Downloaded,
+    Failed,
+}
+
+/// the outside end of a feed task
+#[derive(Debug, Clone)]
+pub struct FeedControl {
+    pub id: usize,
+    pub name: String,
+    pub url: String,
+    paused: Arc<AtomicBool>,
+    sender: mpsc::Sender<FeedCommand>,
+}
+
+/// the feed task's end of a [`FeedControl`]
+#[derive(Debug)]
+pub struct FeedCommands {
+    pub(crate) paused: Arc<AtomicBool>,
+    pub(crate) receiver: mpsc::Receiver<FeedCommand>,
+}
+
+pub fn channel(id: usize, name: &str, url: &str) -> (FeedControl, FeedCommands) {
+    let paused = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel(16);
+
+    let control = FeedControl {
+        id,
+        name: name.to_string(),
+        url: url.to_string(),
+        paused: Arc::clone(&paused),
+        sender,
+    };
+
+    (control, FeedCommands { paused, receiver })
+}
+
+impl FeedControl {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// stop polling the feed until resumed. commands are still handled
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> FeedCommand) -> Result<T, Error> {
+        let (reply, response) = oneshot::channel();
+
+        self.sender
+            .send(command(reply))
+            .await
+            .map_err(|_| Error::TaskStopped(self.name.clone()))?;
+
+        response
+            .await
+            .map_err(|_| Error::TaskStopped(self.name.clone()))?
+    }
+
+    /// poll the feed now, waiting for the poll to finish
+    pub async fn poll(&self) -> Result<(), Error> {
+        self.request(FeedCommand::Poll).await
+    }
+
+    pub async fn matchers(&self) -> Result<Vec<TorrentMatch>, Error> {
+        self.request(FeedCommand::ListMatchers).await
+    }
+
+    /// returns the index of the new matcher
+    pub async fn add_matcher(&self, matcher: TorrentMatch) -> Result<usize, Error> {
+        self.request(|reply| FeedCommand::AddMatcher(matcher, reply))
+            .await
+    }
+
+    pub async fn update_matcher(&self, index: usize, matcher: TorrentMatch) -> Result<(), Error> {
+        self.request(|reply| FeedCommand::UpdateMatcher(index, matcher, reply))
+            .await
+    }
+
+    pub async fn remove_matcher(&self, index: usize) -> Result<TorrentMatch, Error> {
+        self.request(|reply| FeedCommand::RemoveMatcher(index, reply))
+            .await
+    }
+
+    pub async fn retry(&self, item_hash: u64) -> Result<(), Error> {
+        self.request(|reply| FeedCommand::Retry(item_hash, reply))
+            .await
+    }
+
+    pub async fn items(&self) -> Result<Vec<ItemView>, Error> {
+        self.request(FeedCommand::Items).await
+    }
+
+    /// which items of the last fetch `matcher` would accept
+    pub async fn preview(&self, matcher: TorrentMatch) -> Result<Vec<ItemView>, Error> {
+        self.request(|reply| FeedCommand::Preview(matcher, reply))
+            .await
+    }
+}
+
+/// every running feed task
+#[derive(Debug, Default)]
+pub struct Controller {
+    feeds: Vec<FeedControl>,
+}
+
+impl Controller {
+    pub fn new(feeds: Vec<FeedControl>) -> Self {
+        Self { feeds }
+    }
+
+    pub fn feeds(&self) -> &[FeedControl] {
+        &self.feeds
+    }
+
+    pub fn feed(&self, id: usize) -> Result<&FeedControl, Error> {
+        self.feeds
+            .iter()
+            .find(|x| x.id == id)
+            .ok_or_else(|| Error::NotFound(format! {"feed {}", id}))
+    }
+}
@@ -0,0 +1,386 @@
+//!
+//! periodic email digests of events over smtp
+//!
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::events::{Event, EventKind, EventSender};
+use super::secret::Secret;
+use super::supervisor::Shutdown;
+use super::Error;
+
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    /// defaults to the usual port for `security`
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// seconds between digests, once a day by default
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// only include these events, all of them by default
+    pub events: Option<Vec<EventKind>>,
+    /// send a digest even when nothing happened
+    #[serde(default)]
+    pub send_empty: bool,
+}
+
+fn default_interval() -> u64 {
+    60 * 60 * 24
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// plain connection upgraded with STARTTLS, port 587
+    #[default]
+    Starttls,
+    /// tls from the start, port 465
+    Tls,
+    /// no encryption at all, for local relays and test sinks
+    None,
+}
+
+// torrents listed in one digest. a digest that cannot be sent keeps growing until
+// it can, so past this the rest are only counted
+const DIGEST_LENGTH: usize = 1000;
+
+#[derive(Debug, Default)]
+struct Digest {
+    // (feed, matcher) -> titles
+    downloads: BTreeMap<(String, String), Vec<String>>,
+    paused: Vec<String>,
+    title_banned: Vec<String>,
+    // feed -> (failures, last error)
+    failing: BTreeMap<String, (u32, String)>,
+    // torrents listed above, and the ones left out once there were too many
+    listed: usize,
+    left_out: usize,
+}
+
+impl Digest {
+    fn add(&mut self, event: Event) {
+        if !matches!(event, Event::FeedFailing { .. }) {
+            if self.listed >= DIGEST_LENGTH {
+                self.left_out += 1;
+                return;
+            }
+            self.listed += 1;
+        }
+
+        match event {
+            Event::Downloaded {
+                feed,
+                matcher,
+                title,
+                ..
+            } => self
+                .downloads
+                .entry((feed, matcher))
+                .or_default()
+                .push(title),
+            Event::Paused { name, .. } => self.paused.push(name),
+            Event::TitleBanned { name, .. } => self.title_banned.push(name),
+            Event::FeedFailing {
+                feed,
+                failures,
+                error,
+            } => {
+                self.failing.insert(feed, (failures, error));
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.listed == 0 && self.failing.is_empty()
+    }
+
+    fn body(&self) -> String {
+        let mut body = String::new();
+
+        let downloaded: usize = self.downloads.values().map(|x| x.len()).sum();
+        body += &format! {"downloaded {} torrents\n", downloaded};
+        for ((feed, matcher), titles) in &self.downloads {
+            body += &format! {"\n  {} -> {}\n", feed, matcher};
+            for title in titles {
+                body += &format! {"    {}\n", title};
+            }
+        }
+
+        body += &format! {"\npaused {} torrents without kept trackers\n", self.paused.len()};
+        for name in &self.paused {
+            body += &format! {"    {}\n", name};
+        }
+
+        body += &format! {"\npaused {} torrents with banned titles\n", self.title_banned.len()};
+        for name in &self.title_banned {
+            body += &format! {"    {}\n", name};
+        }
+
+        if !self.failing.is_empty() {
+            body += &format! {"\n{} feeds failing\n", self.failing.len()};
+            for (feed, (failures, error)) in &self.failing {
+                body += &format! {"    {} ({} failures): {}\n", feed, failures, error};
+            }
+        }
+
+        if self.left_out > 0 {
+            body += &format! {
+                "\n{} more torrents were left out of this digest, which lists at most {}\n",
+                self.left_out, DIGEST_LENGTH
+            };
+        }
+
+        body
+    }
+}
+
+/// collect events and mail them out as a digest every `interval`, and once more with
+/// whatever is left when `shutdown` is triggered
+pub fn spawn_digest(
+    config: &EmailConfig,
+    events: &EventSender,
+    shutdown: &Shutdown,
+) -> JoinHandle<()> {
+    let config = config.clone();
+    let mut receiver = events.subscribe();
+    let shutdown = shutdown.clone();
+
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.interval.max(60));
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        let mut digest = Digest::default();
+
+        loop {
+            tokio::select! {
+                biased;
+                event = receiver.recv() => match event {
+                    Ok(event) => {
+                        if config.wants(&event) {
+                            digest.add(event);
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        println! {"email digest fell behind and skipped {} events", missed};
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => {
+                    if digest.is_empty() && !config.send_empty {
+                        continue;
+                    }
+
+                    // on failure the events are kept and go out with the next digest
+                    match config.send(&digest).await {
+                        Ok(()) => digest = Digest::default(),
+                        Err(e) => println! {"could not send email digest: {}", e},
+                    }
+                }
+                _ = shutdown.wait() => {
+                    if !digest.is_empty() {
+                        if let Err(e) = config.send(&digest).await {
+                            println! {"could not send the last email digest: {}", e};
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+    })
+}
+
+impl EmailConfig {
+    fn wants(&self, event: &Event) -> bool {
+        match &self.events {
+            Some(kinds) => kinds.contains(&event.kind()),
+            None => true,
+        }
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, Error> {
+        let mut builder = match self.security {
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host).port(25)
+            }
+        };
+
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+
+        if let Some(username) = &self.username {
+            let password = match &self.password {
+                Some(password) => password.resolve()?,
+                None => String::new(),
+            };
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(builder.build())
+    }
+
+    async fn send(&self, digest: &Digest) -> Result<(), Error> {
+        let mut message = Message::builder()
+            .from(self.from.parse()?)
+            .subject("autodl-rss digest")
+            .header(ContentType::TEXT_PLAIN);
+
+        for to in &self.to {
+            message = message.to(to.parse()?);
+        }
+
+        let message = message.body(digest.body())?;
+        self.transport()?.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // just enough of an smtp server to take one message, which is returned with the
+    // recipients it was sent to
+    async fn smtp_sink(listener: TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut recipients = Vec::new();
+        let mut data = String::new();
+
+        writer.write_all(b"220 sink ready\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 sink\r\n"
+            } else if command.starts_with("RCPT TO:") {
+                recipients.push(line[8..].trim().to_string());
+                b"250 ok\r\n"
+            } else if command.starts_with("DATA") {
+                writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    data += &line;
+                    data += "\n";
+                }
+                b"250 queued\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+
+        (recipients, data)
+    }
+
+    fn downloaded(feed: &str, matcher: &str, title: &str) -> Event {
+        Event::Downloaded {
+            feed: feed.to_string(),
+            matcher: matcher.to_string(),
+            title: title.to_string(),
+            save_folder: "/downloads".to_string(),
+        }
+    }
+
+    #[test]
+    fn downloads_are_grouped_by_matcher() {
+        let mut digest = Digest::default();
+        assert!(digest.is_empty());
+
+        // both matchers save to the same folder, but are listed on their own
+        digest.add(downloaded("tracker", "shows", "Show S01E01"));
+        digest.add(downloaded("tracker", "movies", "Movie 2020"));
+        digest.add(downloaded("tracker", "shows", "Show S01E02"));
+        digest.add(Event::FeedFailing {
+            feed: "other".to_string(),
+            failures: 2,
+            error: "timed out".to_string(),
+        });
+        digest.add(Event::FeedFailing {
+            feed: "other".to_string(),
+            failures: 3,
+            error: "timed out".to_string(),
+        });
+        assert!(!digest.is_empty());
+
+        let body = digest.body();
+        assert!(body.starts_with("downloaded 3 torrents\n"));
+        assert!(body.contains(
+            "  tracker -> movies\n    Movie 2020\n\n  tracker -> shows\n    Show S01E01\n    Show S01E02\n"
+        ));
+        assert!(body.contains("1 feeds failing\n    other (3 failures): timed out\n"));
+    }
+
+    #[test]
+    fn digests_stop_growing() {
+        let mut digest = Digest::default();
+        for n in 0..DIGEST_LENGTH + 5 {
+            digest.add(downloaded("tracker", "shows", &format! {"Show {}", n}));
+        }
+        // failing feeds are one line each, so they are always kept
+        digest.add(Event::FeedFailing {
+            feed: "tracker".to_string(),
+            failures: 3,
+            error: "timed out".to_string(),
+        });
+
+        let body = digest.body();
+        assert!(body.starts_with(&format! {"downloaded {} torrents\n", DIGEST_LENGTH}));
+        assert!(!body.contains(&format! {"Show {}\n", DIGEST_LENGTH}));
+        assert!(body.contains("1 feeds failing\n"));
+        assert!(body.ends_with(&format! {
+            "\n5 more torrents were left out of this digest, which lists at most {}\n",
+            DIGEST_LENGTH
+        }));
+    }
+
+    #[tokio::test]
+    async fn digest_is_sent_to_smtp_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(smtp_sink(listener));
+
+        let config: EmailConfig = serde_yaml::from_str(&format! {
+            "host: 127.0.0.1\nport: {}\nsecurity: none\nfrom: autodl@example.com\n\
+             to: [me@example.com, you@example.com]",
+            port
+        })
+        .unwrap();
+
+        let mut digest = Digest::default();
+        digest.add(downloaded("tracker", "shows", "Show S01E01"));
+        config.send(&digest).await.unwrap();
+
+        let (recipients, data) = sink.await.unwrap();
+        assert_eq!(recipients, ["<me@example.com>", "<you@example.com>"]);
+        assert!(data.contains("Subject: autodl-rss digest"));
+        assert!(data.contains("tracker -> shows"));
+        assert!(data.contains("Show S01E01"));
+    }
+}
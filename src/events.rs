@@ -0,0 +1,120 @@
+//!
+//! things that happen while running, published to anything that wants to report on them
+//!
+
+use super::redact;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // a torrent from a feed was sent to qbittorrent
+    Downloaded {
+        feed: String,
+        matcher: String,
+        title: String,
+        save_folder: String,
+    },
+    // a completed torrent was paused because none of its trackers are kept
+    Paused {
+        name: String,
+        hash: String,
+    },
+    // a torrent was paused because its title is banned
+    TitleBanned {
+        name: String,
+        hash: String,
+    },
+    // a feed reached the configured number of consecutive failures
+    FeedFailing {
+        feed: String,
+        failures: u32,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Downloaded,
+    Paused,
+    TitleBanned,
+    FeedFailing,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Downloaded { .. } => EventKind::Downloaded,
+            Event::Paused { .. } => EventKind::Paused,
+            Event::TitleBanned { .. } => EventKind::TitleBanned,
+            Event::FeedFailing { .. } => EventKind::FeedFailing,
+        }
+    }
+
+    /// one line human readable description
+    pub fn message(&self) -> String {
+        match self {
+            Event::Downloaded { feed, title, .. } => {
+                format! {"downloading {} from {}", title, feed}
+            }
+            Event::Paused { name, .. } => {
+                format! {"paused {}: no kept trackers", name}
+            }
+            Event::TitleBanned { name, .. } => {
+                format! {"paused {}: banned title", name}
+            }
+            Event::FeedFailing {
+                feed,
+                failures,
+                error,
+            } => format! {"feed {} has failed {} times in a row: {}", feed, failures, error},
+        }
+    }
+
+    /// the torrent or feed this event is about
+    pub fn subject(&self) -> &str {
+        match self {
+            Event::Downloaded { title, .. } => title,
+            Event::Paused { name, .. } | Event::TitleBanned { name, .. } => name,
+            Event::FeedFailing { feed, .. } => feed,
+        }
+    }
+}
+
+/// cheap to clone handle that publishes events to every subscriber
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventSender {
+    pub fn new() -> Self {
+        // subscribers that fall this far behind start losing the oldest events
+        let (sender, _) = broadcast::channel(1024);
+        Self { sender }
+    }
+
+    pub fn send(&self, mut event: Event) {
+        // errors end up in notifications and emails, which leave this machine
+        if let Event::FeedFailing { error, .. } = &mut event {
+            *error = redact::text(error);
+        }
+
+        // an error only means nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,191 @@
+//!
+//! recent items matched by feeds and what happened to them
+//!
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::redact;
+use super::Error;
+
+use serde::{Deserialize, Serialize};
+
+// entries kept before the oldest are dropped
+const HISTORY_LENGTH: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub feed: String,
+    pub title: String,
+    pub matcher: String,
+    pub item_hash: u64,
+    // unix time in seconds
+    pub time: u64,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Downloaded,
+    Failed { error: String },
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Mutex<VecDeque<HistoryEntry>>,
+    // saved to after every new entry, so that a crash loses nothing
+    path: Option<PathBuf>,
+    // held for a whole save, so that feeds recording at the same time save one after
+    // another and an older copy of the entries never replaces a newer one
+    saving: Mutex<()>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// read the history saved at `path`, starting empty if there is none. every
+    /// entry recorded after this is saved there too
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path: Some(path.to_path_buf()),
+                    ..Self::default()
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries: VecDeque<HistoryEntry> =
+            serde_json::from_reader(std::io::BufReader::new(file))?;
+        while entries.len() > HISTORY_LENGTH {
+            entries.pop_front();
+        }
+
+        Ok(Self {
+            entries: Mutex::new(entries),
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let _saving = self.saving.lock().expect("history save lock poisoned");
+        let entries = self.entries.lock().expect("history lock poisoned").clone();
+        super::save_json(path, &entries)
+    }
+
+    pub fn record(&self, feed: &str, title: &str, matcher: &str, item_hash: u64, outcome: Outcome) {
+        let outcome = match outcome {
+            Outcome::Failed { error } => Outcome::Failed {
+                error: redact::text(&error),
+            },
+            x => x,
+        };
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        {
+            let mut entries = self.entries.lock().expect("history lock poisoned");
+            if entries.len() >= HISTORY_LENGTH {
+                entries.pop_front();
+            }
+            entries.push_back(HistoryEntry {
+                feed: feed.to_string(),
+                title: title.to_string(),
+                matcher: matcher.to_string(),
+                item_hash,
+                time,
+                outcome,
+            });
+        }
+
+        if let Some(path) = &self.path {
+            if let Err(e) = self.save(path) {
+                println! {"could not save download history to {}: {}", path.display(), e};
+            }
+        }
+    }
+
+    /// newest entries first
+    pub fn recent(&self) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .expect("history lock poisoned")
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_saved_as_they_are_recorded() {
+        let dir = std::env::temp_dir().join(format! {"autodl-history-{}", std::process::id()});
+        let path = dir.join("history.json");
+
+        let history = History::load(&path).unwrap();
+        history.record("tracker", "Show S01E01", "shows", 1, Outcome::Downloaded);
+        history.record(
+            "tracker",
+            "Show S01E02",
+            "shows",
+            2,
+            Outcome::Failed {
+                error: "timed out".to_string(),
+            },
+        );
+
+        // read back without the first history ever saving on its own
+        let loaded = History::load(&path).unwrap().recent();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].title, "Show S01E02");
+        assert_eq!(loaded[1].outcome, Outcome::Downloaded);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn feeds_can_record_at_the_same_time() {
+        let dir =
+            std::env::temp_dir().join(format! {"autodl-history-tasks-{}", std::process::id()});
+        let path = dir.join("history.json");
+        let history = std::sync::Arc::new(History::load(&path).unwrap());
+
+        let tasks = (0..8)
+            .map(|feed| {
+                let history = std::sync::Arc::clone(&history);
+                tokio::spawn(async move {
+                    for item in 0..20 {
+                        let title = format! {"feed {} item {}", feed, item};
+                        history.record("tracker", &title, "shows", item, Outcome::Downloaded);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let loaded = History::load(&path).unwrap().recent();
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the last save has every entry, and no temporary file is left behind
+        assert_eq!(loaded.len(), 160);
+        assert_eq!(files, 1);
+    }
+}
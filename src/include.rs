@@ -0,0 +1,450 @@
+//!
+//! config split over several files, joined together with `include:`, in yaml, toml
+//! or json
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::redact;
+use super::secret;
+use super::{Context, Error};
+
+use serde_yaml::{Mapping, Value};
+
+/// read the config at `path` along with everything it includes, as one document
+pub(crate) fn load(path: &Path) -> Result<Value, Error> {
+    let mut merged = Merged::default();
+    merged.load(path)?;
+    Ok(Value::Mapping(merged.root))
+}
+
+#[derive(Default)]
+struct Merged {
+    root: Mapping,
+    // which file set each top level key, feed name, feed url and named matcher, for
+    // conflicts
+    origins: HashMap<String, PathBuf>,
+    // files that were already merged, so that a file included twice (or including
+    // itself) is only read once
+    loaded: HashSet<PathBuf>,
+}
+
+impl Merged {
+    fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let canonical = path
+            .canonicalize()
+            .config_context(path.display().to_string())?;
+        if !self.loaded.insert(canonical) {
+            return Ok(());
+        }
+
+        let mut value = read(path).config_context(path.display().to_string())?;
+        secret::interpolate(&mut value).config_context(path.display().to_string())?;
+
+        let mut mapping = match value {
+            Value::Mapping(mapping) => mapping,
+            Value::Null => Mapping::new(),
+            _ => {
+                return Err(Error::Include(
+                    "the file is not a map of settings".to_string(),
+                ))
+                .config_context(path.display().to_string())
+            }
+        };
+
+        let includes = mapping.remove("include");
+        self.merge(mapping, path)?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for include in include_list(includes).config_context(path.display().to_string())? {
+            for file in expand(&base.join(include)).config_context(path.display().to_string())? {
+                self.load(&file)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge(&mut self, mapping: Mapping, path: &Path) -> Result<(), Error> {
+        for (key, value) in mapping {
+            let name = key.as_str().unwrap_or_default().to_string();
+
+            match (name.as_str(), value) {
+                // feeds from every file are polled, as long as their names differ and
+                // they are not the same feed written out twice
+                ("feeds", Value::Sequence(feeds)) => {
+                    for feed in &feeds {
+                        if let Some(feed) = feed.get("name").and_then(|x| x.as_str()) {
+                            self.claim(format! {"feeds.{}", feed}, path)?;
+                        }
+                    }
+                    for url in feeds.iter().filter_map(|x| x.get("url")?.as_str()) {
+                        self.claim_across(format! {"feeds with url {}", redact::url(url)}, path)?;
+                    }
+
+                    match self.root.get_mut("feeds") {
+                        Some(Value::Sequence(existing)) => existing.extend(feeds),
+                        _ => {
+                            self.root.insert(key, Value::Sequence(feeds));
+                        }
+                    }
+                }
+                // so are named matchers, which can be used by feeds in any file
+                ("matchers", Value::Mapping(matchers)) => {
+                    for matcher in matchers.keys() {
+                        let matcher = matcher.as_str().unwrap_or_default();
+                        self.claim(format! {"matchers.{}", matcher}, path)?;
+                    }
+
+                    match self.root.get_mut("matchers") {
+                        Some(Value::Mapping(existing)) => existing.extend(matchers),
+                        _ => {
+                            self.root.insert(key, Value::Mapping(matchers));
+                        }
+                    }
+                }
+                // everything else can only be set once, or to the same value
+                (_, value) => match self.root.get(&key) {
+                    Some(existing) if *existing == value => (),
+                    Some(_) => self.claim(name, path)?,
+                    None => {
+                        self.origins.insert(name, path.to_path_buf());
+                        self.root.insert(key, value);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // record that `path` sets `key`, unless another file already does
+    fn claim(&mut self, key: String, path: &Path) -> Result<(), Error> {
+        if let Some(first) = self.origins.get(&key) {
+            return Err(Error::ConfigConflict {
+                key,
+                first: first.display().to_string(),
+                second: path.display().to_string(),
+            });
+        }
+
+        self.origins.insert(key, path.to_path_buf());
+        Ok(())
+    }
+
+    // like `claim`, but `path` may set `key` more than once itself
+    fn claim_across(&mut self, key: String, path: &Path) -> Result<(), Error> {
+        match self.origins.get(&key) {
+            Some(first) if first == path => Ok(()),
+            _ => self.claim(key, path),
+        }
+    }
+}
+
+/// read a single config file as a yaml document, whichever format it is written in
+pub(crate) fn read(path: &Path) -> Result<Value, Error> {
+    let text = std::fs::read_to_string(path)?;
+
+    let value = match Format::of(path) {
+        Format::Yaml => serde_yaml::from_str(&text)?,
+        Format::Toml => toml::from_str(&text)?,
+        Format::Json => serde_json::from_str(&text)?,
+    };
+
+    Ok(value)
+}
+
+/// write a yaml document to `path` in the format its extension asks for
+pub(crate) fn write(path: &Path, value: &Value) -> Result<(), Error> {
+    let text = match Format::of(path) {
+        Format::Yaml => serde_yaml::to_string(value)?,
+        Format::Toml => toml::to_string_pretty(value)?,
+        Format::Json => serde_json::to_string_pretty(value)?,
+    };
+
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    // anything that is not .toml or .json is read as yaml
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+// `include:` takes a single path or a list of them
+fn include_list(value: Option<Value>) -> Result<Vec<String>, Error> {
+    let paths = match value {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::String(path)) => vec![path],
+        Some(Value::Sequence(paths)) => paths
+            .into_iter()
+            .map(|x| match x {
+                Value::String(path) => Ok(path),
+                _ => Err(Error::Include(
+                    "`include` entries have to be paths".to_string(),
+                )),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(Error::Include(
+                "`include` has to be a path or a list of paths".to_string(),
+            ))
+        }
+    };
+
+    Ok(paths)
+}
+
+// the files an include refers to: the file itself, every yaml, toml or json file in
+// a directory, or the files matching a `*` in the file name, in name order
+fn expand(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let (dir, pattern) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else if let Some((prefix, suffix)) = file_name.split_once('*') {
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        (dir, Some((prefix.to_string(), suffix.to_string())))
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?.path();
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = entry
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let wanted = match &pattern {
+            Some((prefix, suffix)) => {
+                name.len() >= prefix.len() + suffix.len()
+                    && name.starts_with(prefix.as_str())
+                    && name.ends_with(suffix.as_str())
+            }
+            None => [".yaml", ".yml", ".toml", ".json"]
+                .iter()
+                .any(|x| name.ends_with(x)),
+        };
+
+        if wanted {
+            files.push(entry);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory holding `files`, removed again when dropped
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format! {
+                "autodl-include-{}-{}",
+                name,
+                std::process::id()
+            });
+            let _ = std::fs::remove_dir_all(&dir);
+
+            for (file, text) in files {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, text).unwrap();
+            }
+            Self(dir)
+        }
+
+        fn load(&self, file: &str) -> Result<Value, Error> {
+            load(&self.0.join(file))
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn feed_urls(value: &Value) -> Vec<&str> {
+        value["feeds"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|x| x["url"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn files_are_merged() {
+        let dir = Dir::new(
+            "merge",
+            &[
+                (
+                    "config.yaml",
+                    "include: [qbit.toml, feeds]\nfeeds: [{url: https://a.example/rss}]\n\
+                     matchers: {tv: {save_folder: /tv}}\nstartup_stagger: 10",
+                ),
+                ("qbit.toml", "startup_stagger = 10\n[qbittorrent]\nusername = \"admin\""),
+                ("feeds/b.json", r#"{"feeds": [{"url": "https://b.example/rss"}]}"#),
+                (
+                    "feeds/c.yml",
+                    "feeds: [{url: https://c.example/rss}]\nmatchers: {films: {save_folder: /films}}",
+                ),
+                ("feeds/notes.txt", "not a config"),
+            ],
+        );
+
+        let value = dir.load("config.yaml").unwrap();
+        assert_eq!(
+            feed_urls(&value),
+            [
+                "https://a.example/rss",
+                "https://b.example/rss",
+                "https://c.example/rss"
+            ]
+        );
+        assert_eq!(value["matchers"].as_mapping().unwrap().len(), 2);
+        assert_eq!(value["qbittorrent"]["username"], "admin");
+        assert!(value.get("include").is_none());
+    }
+
+    #[test]
+    fn globs_match_the_file_name() {
+        let dir = Dir::new(
+            "glob",
+            &[
+                ("config.yaml", "include: conf.d/feed-*.yaml"),
+                (
+                    "conf.d/feed-b.yaml",
+                    "feeds: [{url: https://b.example/rss}]",
+                ),
+                (
+                    "conf.d/feed-a.yaml",
+                    "feeds: [{url: https://a.example/rss}]",
+                ),
+                ("conf.d/other.yaml", "feeds: [{url: https://x.example/rss}]"),
+                (
+                    "conf.d/feed-c.yaml.bak",
+                    "feeds: [{url: https://y.example/rss}]",
+                ),
+            ],
+        );
+
+        let value = dir.load("config.yaml").unwrap();
+        // in name order
+        assert_eq!(
+            feed_urls(&value),
+            ["https://a.example/rss", "https://b.example/rss"]
+        );
+    }
+
+    #[test]
+    fn cycles_are_read_once() {
+        let dir = Dir::new(
+            "cycle",
+            &[
+                (
+                    "a.yaml",
+                    "include: b.yaml\nfeeds: [{url: https://a.example/rss}]",
+                ),
+                (
+                    "b.yaml",
+                    "include: [a.yaml, b.yaml]\nfeeds: [{url: https://b.example/rss}]",
+                ),
+            ],
+        );
+
+        let value = dir.load("a.yaml").unwrap();
+        assert_eq!(
+            feed_urls(&value),
+            ["https://a.example/rss", "https://b.example/rss"]
+        );
+    }
+
+    #[test]
+    fn conflicts_name_both_files() {
+        let conflict = |name: &str, other: &str| {
+            let dir = Dir::new(
+                name,
+                &[
+                    (
+                        "config.yaml",
+                        "include: other.yaml\nstartup_stagger: 10\nmatchers: {tv: {save_folder: /tv}}\n\
+                         feeds: [{name: one, url: 'https://a.example/rss?passkey=0123456789'}]",
+                    ),
+                    ("other.yaml", other),
+                ],
+            );
+            match dir.load("config.yaml") {
+                Err(Error::ConfigConflict { key, first, second }) => {
+                    assert!(first.ends_with("config.yaml") && second.ends_with("other.yaml"));
+                    key
+                }
+                x => panic!("expected a conflict, got {:?}", x),
+            }
+        };
+
+        assert_eq!(
+            conflict("setting", "startup_stagger: 20"),
+            "startup_stagger"
+        );
+        assert_eq!(
+            conflict("matcher", "matchers: {tv: {save_folder: /shows}}"),
+            "matchers.tv"
+        );
+        assert_eq!(
+            conflict("feed", "feeds: [{name: one, url: https://b.example/rss}]"),
+            "feeds.one"
+        );
+        assert_eq!(
+            conflict(
+                "url",
+                "feeds: [{name: two, url: 'https://a.example/rss?passkey=0123456789'}]"
+            ),
+            "feeds with url https://a.example/rss?passkey=REDACTED"
+        );
+    }
+
+    #[test]
+    fn same_settings_and_urls_in_one_file_are_fine() {
+        let dir = Dir::new(
+            "same",
+            &[
+                (
+                    "config.yaml",
+                    "include: other.yaml\nstartup_stagger: 10\nfeeds:\n\
+                     - {url: https://a.example/rss}\n- {url: https://a.example/rss}",
+                ),
+                ("other.yaml", "startup_stagger: 10"),
+            ],
+        );
+
+        assert_eq!(feed_urls(&dir.load("config.yaml").unwrap()).len(), 2);
+    }
+}
@@ -1,29 +1,242 @@
+pub mod backoff;
+pub mod client;
+pub mod control;
+pub mod email;
+pub mod events;
+pub mod history;
+pub mod include;
+pub mod metrics;
+pub mod migrate;
+pub mod mirror;
 pub mod monitor;
+pub mod notify;
+pub mod qbit_api;
+pub mod ratelimit;
+pub mod redact;
 pub mod rss;
+pub mod secret;
+pub mod server;
+pub mod status;
+pub mod supervisor;
+pub mod torrents;
 pub mod yaml;
 
-#[derive(Debug, thiserror::Error )]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("")]
-    Reqwest(#[from] reqwest::Error),
-    #[error("")]
+    #[error("http request failed: {0}")]
+    Reqwest(reqwest::Error),
+    #[error("could not parse rss xml: {0}")]
     Serde(#[from] quick_xml::DeError),
-    #[error("")]
+    #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
-    #[error("")]
+    // also raised for toml and json configs, which are checked as a yaml document
+    #[error("invalid config: {0}")]
     YamlError(#[from] serde_yaml::Error),
-    #[error("")]
+    #[error("invalid toml: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[error("could not write toml: {0}")]
+    TomlWriteError(#[from] toml::ser::Error),
+    #[error("invalid json: {0}")]
     JsonError(#[from] serde_json::Error),
-    #[error("")]
+    #[error("rss document did not have the expected layout")]
     SerdeGeneral,
-    #[error("")]
-    QbitError(#[from] qbittorrent::error::Error),
-    #[error("")]
-    SerdeMissing,
-    #[error("")]
-    MissingBytes,
-    #[error("")]
+    #[error("rss document is missing the `{0}` field")]
+    SerdeMissing(&'static str),
+    #[error("response body from {url} was empty")]
+    MissingBytes { url: String },
+    #[error("access denied with status {status} by {url} (passkey or cookie may have expired): {snippet}")]
+    AccessDenied {
+        status: u16,
+        url: String,
+        snippet: String,
+    },
+    #[error("server error with status {status} from {url}: {snippet}")]
+    ServerError {
+        status: u16,
+        url: String,
+        snippet: String,
+    },
+    #[error("unexpected status {status} from {url}: {snippet}")]
+    UnexpectedStatus {
+        status: u16,
+        url: String,
+        snippet: String,
+    },
+    #[error("expected rss but got an html page with status {status} from {url}: {snippet}")]
+    HtmlResponse {
+        status: u16,
+        url: String,
+        snippet: String,
+    },
+    #[error("invalid http header value: {0}")]
     InvalidHeader(#[from] http::header::InvalidHeaderValue),
-    #[error("the configuration file was missing from all locations")]
-    ConfigMissing,
+    #[error("invalid http header name: {0}")]
+    InvalidHeaderName(#[from] http::header::InvalidHeaderName),
+    #[error("could not read secret from {location}: {reason}")]
+    Secret { location: String, reason: String },
+    #[error("the configuration file was missing from all locations: {}", .0.join(", "))]
+    ConfigMissing(Vec<String>),
+    #[error("rate limited by server with status {status}")]
+    RateLimited {
+        status: u16,
+        retry_after: Option<std::time::Duration>,
+    },
+    #[error("could not send email: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("could not build email: {0}")]
+    Email(#[from] lettre::error::Error),
+    #[error("invalid email address: {0}")]
+    EmailAddress(#[from] lettre::address::AddressError),
+    #[error("http server error: {0}")]
+    Server(#[from] hyper::Error),
+    #[error("{0} does not exist")]
+    NotFound(String),
+    #[error("invalid request: {0}")]
+    BadRequest(String),
+    #[error("save folder {0} is not inside any of the server's `save_roots`")]
+    SaveFolder(String),
+    #[error("the task for feed {0} is no longer running")]
+    TaskStopped(String),
+    #[error("feed {url}: {source}")]
+    Feed {
+        url: String,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("item \"{title}\": {source}")]
+    Item {
+        title: String,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("more than one {kind} is named {name}")]
+    DuplicateName { kind: &'static str, name: String },
+    #[error("feed {feed} refers to matcher {name}, which is not in `matchers`")]
+    UnknownMatcher { feed: String, name: String },
+    #[error("`{key}` is set in both {first} and {second}")]
+    ConfigConflict {
+        key: String,
+        first: String,
+        second: String,
+    },
+    #[error("{0}")]
+    Include(String),
+    #[error("config {path}: {source}")]
+    Config {
+        path: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+// reqwest puts the request url in its errors, which often carries a passkey
+impl From<reqwest::Error> for Error {
+    fn from(mut error: reqwest::Error) -> Self {
+        if let Some(url) = error.url_mut() {
+            redact::url_in_place(url);
+        }
+        Error::Reqwest(error)
+    }
+}
+
+impl Error {
+    /// whether the operation that produced this error is worth trying again later.
+    /// network hiccups and server side failures are retryable, while malformed
+    /// documents or config problems will fail the same way every time
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Reqwest(e) => match e.status() {
+                Some(status) => status.is_server_error() || status.as_u16() == 429,
+                None => !e.is_builder(),
+            },
+            Error::IoError(_)
+            | Error::MissingBytes { .. }
+            | Error::ServerError { .. }
+            | Error::RateLimited { .. } => true,
+            Error::UnexpectedStatus { status, .. } => *status == 408,
+            Error::Smtp(e) => !e.is_permanent(),
+            Error::Feed { source, .. }
+            | Error::Item { source, .. }
+            | Error::Config { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// how long the server asked us to wait before the next request, if it said so
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::RateLimited { retry_after, .. } => *retry_after,
+            Error::Feed { source, .. }
+            | Error::Item { source, .. }
+            | Error::Config { source, .. } => source.retry_after(),
+            _ => None,
+        }
+    }
+
+    pub fn feed<T: Into<String>>(self, url: T) -> Self {
+        Error::Feed {
+            url: redact::url(&url.into()),
+            source: Box::new(self),
+        }
+    }
+
+    pub fn item<T: Into<String>>(self, title: T) -> Self {
+        Error::Item {
+            title: title.into(),
+            source: Box::new(self),
+        }
+    }
+
+    pub fn config<T: Into<String>>(self, path: T) -> Self {
+        Error::Config {
+            path: path.into(),
+            source: Box::new(self),
+        }
+    }
+}
+
+/// attach context about where an error happened to the error of a `Result`
+pub trait Context<T> {
+    fn feed_context<S: Into<String>>(self, url: S) -> Result<T, Error>;
+    fn item_context<S: Into<String>>(self, title: S) -> Result<T, Error>;
+    fn config_context<S: Into<String>>(self, path: S) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn feed_context<S: Into<String>>(self, url: S) -> Result<T, Error> {
+        self.map_err(|e| e.into().feed(url))
+    }
+
+    fn item_context<S: Into<String>>(self, title: S) -> Result<T, Error> {
+        self.map_err(|e| e.into().item(title))
+    }
+
+    fn config_context<S: Into<String>>(self, path: S) -> Result<T, Error> {
+        self.map_err(|e| e.into().config(path))
+    }
+}
+
+/// write `value` to `path` as json, replacing the file in one step so that a crash
+/// never leaves a partial file behind
+pub(crate) fn save_json<T: serde::Serialize>(
+    path: &std::path::Path,
+    value: &T,
+) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // a name of its own for every save, so that two saves never write the same file
+    static SAVES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let save = SAVES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let temp = path.with_extension(format! {"json.{}.{}.tmp", std::process::id(), save});
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&temp)?);
+    serde_json::to_writer(&mut file, value)?;
+    // the data has to be on disk before the rename, or a crash can leave an empty file
+    // under the real name
+    let file = file.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+
+    Ok(())
 }
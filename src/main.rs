@@ -1,64 +1,203 @@
-use std::time::Duration;
+use std::sync::Arc;
 
-use autodl_rss::{monitor, yaml, Error};
+use autodl_rss::{
+    control, events, history, metrics, migrate, monitor, server, status, supervisor, yaml, Error,
+};
+use tokio::sync::Mutex;
+
+// the first of these that exists is used
+const CONFIG_PATHS: &[&str] = &[
+    "/config/config.yaml",
+    "/config/config.toml",
+    "/config/config.json",
+    "config.yaml",
+    "config.toml",
+    "config.json",
+];
 
 async fn start() -> Result<(), Error> {
-    let yaml_data = yaml::FeedManager::from_yaml(&["/config/config.yaml", "config.yaml"])?;
-    println! {"opened config.yaml"}
-    let mut qbit: monitor::QbitMonitor = yaml_data.qbit().await?;
-
-    let feeds = yaml_data.split(&qbit.api);
-
-    feeds.into_iter().for_each(|mut x| {
-        let task = async move {
-            loop {
-                let tmp = x.run_update().await;
-                match tmp {
-                    Ok(countdown) => {
-                        println! {"Finished RSS update for tracker: {}", x.feed().url};
-                        tokio::time::sleep(std::time::Duration::from_secs(countdown as u64)).await
-                    }
-                    Err(e) => {
-                        println! {"main thread error fetching torrents: {:?}", e}
-                        tokio::time::sleep(std::time::Duration::from_secs(60)).await
-                    }
-                }
-            }
+    let yaml_data = yaml::FeedManager::from_yaml(CONFIG_PATHS)?;
+    println! {"opened config"}
+    let events = events::EventSender::new();
+    // stopped after everything else, so that events from stopping tasks are still sent
+    let notifications = supervisor::Shutdown::new();
+    let notifiers = yaml_data.start_notifications(&events, &notifications);
+
+    let metrics = Arc::new(metrics::Metrics::new());
+    let status = Arc::new(status::Status::new());
+
+    let history_path = yaml_data.history_path();
+    let history = match &history_path {
+        Some(path) => history::History::load(path)?,
+        None => history::History::new(),
+    };
+    let history = Arc::new(history);
+
+    let qbit: monitor::QbitMonitor = yaml_data.qbit(&events, &metrics).await?;
+
+    let clients = yaml_data.client_factory();
+    let handles = monitor::FeedHandles {
+        qbit_api: Arc::clone(&qbit.qbit_api),
+        limiter: yaml_data.rate_limiter(),
+        connections: clients.connections(),
+        events: events.clone(),
+        metrics: Arc::clone(&metrics),
+        status: Arc::clone(&status),
+        history: Arc::clone(&history),
+        matcher_names: Arc::new(yaml_data.matcher_names()),
+    };
+    let server_config = yaml_data.server().cloned();
+    let save_roots = Arc::new(yaml_data.save_roots());
+    let shutdown_timeout = yaml_data.shutdown_timeout();
+    let feeds = yaml_data.split(&handles, &clients)?;
+
+    let shutdown = supervisor::Shutdown::new();
+    let mut tasks = Vec::new();
+
+    // tasks keep their state in a mutex outside of the task so that a restart after
+    // a panic picks up where the old task left off
+    let mut controls = Vec::new();
+    for (id, feed) in feeds.into_iter().enumerate() {
+        let (control, commands) = control::channel(id, &feed.feed().name, &feed.feed().url);
+        let name = format! {"feed {}", control.name};
+        let running = {
+            let status = Arc::clone(&status);
+            let feed = control.name.clone();
+            move |running| status.feed_task(&feed, running)
         };
-        tokio::spawn(task);
+        controls.push(control);
+
+        let feed = Arc::new(Mutex::new((feed, commands)));
+        let stop = shutdown.clone();
+        tasks.push(supervisor::supervise(
+            name,
+            shutdown.clone(),
+            running,
+            move || {
+                let feed = Arc::clone(&feed);
+                let stop = stop.clone();
+                async move {
+                    let mut feed = feed.lock().await;
+                    let (feed, commands) = &mut *feed;
+                    feed.run(commands, &stop).await
+                }
+            },
+        ));
         println! {"spawning new task"};
-    });
+    }
 
-    loop {
-        println! {"looping through qbittorrent checks"};
+    if let Some(config) = server_config {
+        let state = server::ServerState {
+            metrics: Arc::clone(&metrics),
+            status: Arc::clone(&status),
+            health: config.health.clone(),
+            control: Arc::new(control::Controller::new(controls)),
+            history: Arc::clone(&history),
+            torrents: Arc::clone(&qbit.torrents),
+            qbit_api: Arc::clone(&qbit.qbit_api),
+            api_token: config.api_token.clone(),
+            save_roots,
+        };
+        tasks.push(server::spawn(&config, state, shutdown.clone())?);
+    }
 
-        // get a list of all hashes
-        if let Err(e) = qbit.sync_qbit().await {
-            println! {"error getting full torrent list hashes"}
-            dbg! {e};
-        }
+    let torrents = Arc::clone(&qbit.torrents);
+    let qbit = Arc::new(Mutex::new(qbit));
+    let stop = shutdown.clone();
+    let running = {
+        let status = Arc::clone(&status);
+        move |running| status.qbit_task(running)
+    };
+    tasks.push(supervisor::supervise(
+        "qbittorrent checks".to_string(),
+        shutdown.clone(),
+        running,
+        move || {
+            let qbit = Arc::clone(&qbit);
+            let status = Arc::clone(&status);
+            let stop = stop.clone();
+            async move { qbit.lock().await.run(&status, &stop).await }
+        },
+    ));
 
-        // pause all torrents from trackers not matching
-        if let Err(e) = qbit.pause_all().await {
-            println! {"there was an error pausing all public torrents"}
-            dbg! {e};
-        }
+    supervisor::signal().await;
+    println! {"shutting down, waiting up to {}s for running tasks", shutdown_timeout.as_secs()};
+    shutdown.trigger();
 
-        // pause all torrents with titles we do not want
-        if let Err(e) = qbit.check_titles().await {
-            println! {"there was an error checking torrent titles"}
-            dbg! {e};
-        }
+    let unfinished = supervisor::join_all(tasks, shutdown_timeout).await;
+    if unfinished > 0 {
+        println! {"{} tasks did not stop in time", unfinished};
+    }
 
-        delay(60).await.await;
+    notifications.trigger();
+    let unfinished = supervisor::join_all(notifiers, shutdown_timeout).await;
+    if unfinished > 0 {
+        println! {"{} notification tasks did not stop in time", unfinished};
     }
+
+    // the history is saved as it is recorded
+    torrents.save()?;
+
+    Ok(())
 }
 
-async fn delay(interval: u64) -> tokio::time::Sleep {
-    tokio::time::sleep(Duration::from_secs(interval))
+// load a config the same way the daemon does, without starting anything
+fn check_config(paths: &[String]) {
+    let paths = if paths.is_empty() {
+        CONFIG_PATHS.to_vec()
+    } else {
+        paths.iter().map(|x| x.as_str()).collect()
+    };
+
+    match yaml::FeedManager::from_yaml(&paths) {
+        Ok(config) => println! {"config is valid: {}", config.summary()},
+        Err(e) => {
+            println! {"config is invalid: {}", e};
+            std::process::exit(1);
+        }
+    }
+}
+
+// rewrite older configs into the current layout
+fn migrate_config(paths: &[String]) {
+    if paths.is_empty() {
+        println! {"usage: autodl-rss migrate-config <path>..."};
+        std::process::exit(1);
+    }
+
+    for path in paths {
+        match migrate::migrate_file(std::path::Path::new(path)) {
+            Ok(migration) => match migration.backup {
+                None => println! {"{}: already up to date", path},
+                Some(backup) => {
+                    for change in migration.changes {
+                        println! {"{}: {}", path, change};
+                    }
+                    println! {
+                        "{}: rewritten without its comments, the original was kept as {}",
+                        path,
+                        backup.display()
+                    };
+                }
+            },
+            Err(e) => {
+                println! {"{}: could not migrate: {}", path, e};
+                std::process::exit(1);
+            }
+        }
+    }
 }
+
 #[tokio::main]
 async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(|x| x.as_str()) {
+        Some("check-config") => return check_config(&args[1..]),
+        Some("migrate-config") => return migrate_config(&args[1..]),
+        Some("schema") => return println! {"{}", yaml::FeedManager::schema()},
+        _ => (),
+    }
+
     println! {"sleeping for 10 seconds"}
     std::thread::sleep(std::time::Duration::from_secs(10));
     let s = start().await;
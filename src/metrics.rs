@@ -0,0 +1,153 @@
+//!
+//! prometheus metrics for feed polling and qbittorrent management
+//!
+
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub feed_polls: IntCounterVec,
+    pub feed_successes: IntCounterVec,
+    pub feed_failures: IntCounterVec,
+    pub last_success: GaugeVec,
+    pub items_parsed: IntCounterVec,
+    pub items_matched: IntCounterVec,
+    pub downloads_added: IntCounterVec,
+    pub torrents_paused: IntCounterVec,
+    pub qbit_latency: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            IntCounterVec::new(Opts::new(name, help).namespace("autodl"), labels)
+                .expect("valid metric definition")
+        };
+
+        let metrics = Self {
+            registry: Registry::new(),
+            feed_polls: counter("feed_polls_total", "feed updates attempted", &["feed"]),
+            feed_successes: counter(
+                "feed_poll_successes_total",
+                "feed updates that succeeded",
+                &["feed"],
+            ),
+            feed_failures: counter(
+                "feed_poll_failures_total",
+                "feed updates that failed",
+                &["feed"],
+            ),
+            last_success: GaugeVec::new(
+                Opts::new(
+                    "feed_last_success_timestamp_seconds",
+                    "unix time of the last successful update of a feed",
+                )
+                .namespace("autodl"),
+                &["feed"],
+            )
+            .expect("valid metric definition"),
+            items_parsed: counter(
+                "items_parsed_total",
+                "rss items read from a feed",
+                &["feed"],
+            ),
+            items_matched: counter(
+                "items_matched_total",
+                "rss items accepted by a matcher",
+                // matcher names are unique across the config, but the same named
+                // matcher can be used by several feeds
+                &["feed", "matcher"],
+            ),
+            downloads_added: counter(
+                "downloads_added_total",
+                "torrents sent to qbittorrent",
+                &["feed"],
+            ),
+            torrents_paused: counter(
+                "torrents_paused_total",
+                "torrents paused by autodl-rss",
+                &["reason"],
+            ),
+            qbit_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "qbittorrent_request_duration_seconds",
+                    "time taken by qbittorrent api requests",
+                )
+                .namespace("autodl"),
+                &["endpoint"],
+            )
+            .expect("valid metric definition"),
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.feed_polls.clone()),
+            Box::new(metrics.feed_successes.clone()),
+            Box::new(metrics.feed_failures.clone()),
+            Box::new(metrics.last_success.clone()),
+            Box::new(metrics.items_parsed.clone()),
+            Box::new(metrics.items_matched.clone()),
+            Box::new(metrics.downloads_added.clone()),
+            Box::new(metrics.torrents_paused.clone()),
+            Box::new(metrics.qbit_latency.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metrics are only registered once");
+        }
+
+        metrics
+    }
+
+    /// time a qbittorrent api call, recorded when the returned timer is dropped
+    pub fn qbit_timer(&self, endpoint: &str) -> HistogramTimer {
+        self.qbit_latency
+            .with_label_values(&[endpoint])
+            .start_timer()
+    }
+
+    /// the prometheus text exposition of every metric
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding of metrics does not fail");
+
+        String::from_utf8(buffer).expect("prometheus text format is utf8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_are_labelled_by_name() {
+        let metrics = Metrics::new();
+        metrics.feed_polls.with_label_values(&["hd-shows"]).inc();
+        metrics
+            .items_matched
+            .with_label_values(&["hd-shows", "shows"])
+            .inc();
+        metrics
+            .items_matched
+            .with_label_values(&["sd-shows", "shows"])
+            .inc_by(2);
+
+        let text = metrics.render();
+        assert!(text.contains("autodl_feed_polls_total{feed=\"hd-shows\"} 1"));
+        assert!(text.contains("autodl_items_matched_total{feed=\"hd-shows\",matcher=\"shows\"} 1"));
+        assert!(text.contains("autodl_items_matched_total{feed=\"sd-shows\",matcher=\"shows\"} 2"));
+    }
+}
@@ -0,0 +1,412 @@
+//!
+//! settings from older layouts of the config, moved to where they are read from now
+//!
+
+use std::path::{Path, PathBuf};
+
+use super::include;
+use super::redact;
+use super::Error;
+
+use serde_yaml::{Mapping, Value};
+
+/// move deprecated settings in a config document to their current place, returning a
+/// description of every one that was moved
+pub(crate) fn canonicalize(value: &mut Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    let root = match value {
+        Value::Mapping(root) => root,
+        _ => return changes,
+    };
+
+    // the readme used to put the trackers at the top level
+    if let Some(trackers) = root.remove("trackers_to_keep") {
+        let qbit = root
+            .entry(Value::from("qbittorrent"))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        if let Value::Mapping(qbit) = qbit {
+            extend(qbit, "trackers", trackers);
+        }
+        changes.push(
+            "`trackers_to_keep` is deprecated, use `trackers` under `qbittorrent`".to_string(),
+        );
+    }
+
+    // and called the matchers of a feed `matchers`, which is now the top level map of
+    // named matchers
+    if let Some(Value::Sequence(feeds)) = root.get_mut("feeds") {
+        for feed in feeds.iter_mut() {
+            let feed = match feed {
+                Value::Mapping(feed) => feed,
+                _ => continue,
+            };
+
+            if let Some(matchers) = feed.remove("matchers") {
+                extend(feed, "matcher", matchers);
+                changes.push(format! {
+                    "`matchers` on feed {} is deprecated, use `matcher`",
+                    describe(feed)
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+/// move matchers that sit under a top-level key of their own, so that feeds can point
+/// at them with a yaml anchor, into `matchers` and have the feeds refer to them by name.
+/// anchors are resolved while reading, so without this every feed would get its own
+/// copy of the matcher written out in full
+fn name_anchored_matchers(value: &mut Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    let root = match value {
+        Value::Mapping(root) => root,
+        _ => return changes,
+    };
+
+    // only matchers that a feed uses as they are, anything else at the top level is
+    // left where it is
+    let used = feed_matchers(root).cloned().collect::<Vec<_>>();
+    let anchored = root
+        .iter()
+        .filter(|(_, value)| value.get("save_folder").is_some() && used.contains(value))
+        .filter_map(|(key, value)| Some((key.as_str()?.to_string(), value.clone())))
+        .collect::<Vec<_>>();
+    if anchored.is_empty() {
+        return changes;
+    }
+
+    let mut matchers = match root.remove("matchers") {
+        Some(Value::Mapping(matchers)) => matchers,
+        Some(other) => {
+            root.insert(Value::from("matchers"), other);
+            return changes;
+        }
+        None => Mapping::new(),
+    };
+
+    for (name, matcher) in anchored {
+        // a different matcher already has the name, so this one stays written out
+        match matchers.get(name.as_str()) {
+            Some(existing) if *existing != matcher => continue,
+            Some(_) => (),
+            None => {
+                matchers.insert(Value::from(name.as_str()), matcher.clone());
+            }
+        }
+        root.remove(name.as_str());
+
+        for item in feed_matchers_mut(root) {
+            if *item == matcher {
+                *item = Value::from(name.as_str());
+            }
+        }
+        changes.push(format! {
+            "matcher `{}` was moved into `matchers`, feeds now refer to it by name",
+            name
+        });
+    }
+
+    root.insert(Value::from("matchers"), Value::Mapping(matchers));
+    changes
+}
+
+// every matcher listed by any feed
+fn feed_matchers(root: &Mapping) -> impl Iterator<Item = &Value> {
+    root.get("feeds")
+        .and_then(|x| x.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|feed| feed.get("matcher")?.as_sequence())
+        .flatten()
+}
+
+fn feed_matchers_mut(root: &mut Mapping) -> impl Iterator<Item = &mut Value> {
+    root.get_mut("feeds")
+        .and_then(|x| x.as_sequence_mut())
+        .into_iter()
+        .flatten()
+        .filter_map(|feed| feed.get_mut("matcher")?.as_sequence_mut())
+        .flatten()
+}
+
+// add the items of `value` to the list at `key`, leaving out ones it already has
+fn extend(mapping: &mut Mapping, key: &str, value: Value) {
+    match (mapping.get_mut(key), value) {
+        (Some(Value::Sequence(existing)), Value::Sequence(items)) => {
+            for item in items {
+                if !existing.contains(&item) {
+                    existing.push(item);
+                }
+            }
+        }
+        (Some(_), _) => (),
+        (None, value) => {
+            mapping.insert(Value::from(key), value);
+        }
+    }
+}
+
+// a feed by its name, or its url when it does not have one yet
+fn describe(feed: &Mapping) -> String {
+    let name = feed.get("name").and_then(|x| x.as_str());
+    let url = feed.get("url").and_then(|x| x.as_str());
+
+    match (name, url) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(url)) => redact::url(url),
+        (None, None) => "without a url".to_string(),
+    }
+}
+
+/// result of migrating one file
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// every setting that was moved
+    pub changes: Vec<String>,
+    /// where the original file was kept, if it was rewritten
+    pub backup: Option<PathBuf>,
+}
+
+/// rewrite the config file at `path` into the current layout, keeping the original
+/// next to it with a `.bak` extension. the file is left alone if nothing changes.
+/// comments are not kept, since the file is written back from the parsed values
+pub fn migrate_file(path: &Path) -> Result<Migration, Error> {
+    // references to environment variables and files are kept as they are
+    let mut value = include::read(path)?;
+
+    let mut changes = canonicalize(&mut value);
+    changes.extend(name_anchored_matchers(&mut value));
+    if changes.is_empty() {
+        return Ok(Migration::default());
+    }
+
+    let backup = backup_path(path);
+    std::fs::copy(path, &backup)?;
+
+    include::write(path, &value)?;
+    Ok(Migration {
+        changes,
+        backup: Some(backup),
+    })
+}
+
+// `<path>.bak`, or `<path>.bak.<n>` so that an earlier backup is never overwritten
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = PathBuf::from(path);
+    backup.as_mut_os_string().push(".bak");
+
+    let mut n = 1;
+    let mut numbered = backup.clone();
+    while numbered.exists() {
+        numbered = backup.clone();
+        numbered.as_mut_os_string().push(format! {".{}", n});
+        n += 1;
+    }
+    numbered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::yaml::FeedManager;
+
+    // the example config from older versions of the readme
+    const LEGACY: &str = "
+# first matcher, referencable by \"*matcher_1\"
+example_matcher: &matcher_1
+  tags_wanted:
+    - [condition1, condition2]
+    - - condition3
+  tags_banned:
+    - [tag1, tag2]
+  save_folder: path/to/torrent/download/location
+
+example_matcher2: &matcher_2
+  tags_wanted:
+    - [tag2, tag1]
+  title_banned:
+    - - banned_title1
+  save_folder: path/to/torrent/download/location
+
+feeds:
+    - url : https://rss_tracker_url_here.com/
+      update_interval: 600
+      matchers:
+        - *matcher_1
+        - *matcher_2
+    - url: https://another_rss_url.com/
+      update_interval: 60
+      matcher:
+        - *matcher_1
+
+qbittorrent:
+    username: admin
+    password: adminadmin
+    address: http://localhost:8080
+
+# torrents from these trackers will not be auto-paused when completed
+trackers_to_keep:
+    - rarbg.to
+    - nyaa.si
+";
+
+    // a fresh directory holding a config.yaml with `text`, removed again when dropped
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str, text: &str) -> Self {
+            let dir = std::env::temp_dir().join(format! {
+                "autodl-migrate-{}-{}",
+                name,
+                std::process::id()
+            });
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("config.yaml"), text).unwrap();
+            Self(dir)
+        }
+
+        fn config(&self) -> PathBuf {
+            self.0.join("config.yaml")
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn legacy_readme_example_is_canonicalized() {
+        let mut value: Value = serde_yaml::from_str(LEGACY).unwrap();
+        let changes = canonicalize(&mut value);
+
+        assert_eq!(changes.len(), 2);
+        assert!(value.get("trackers_to_keep").is_none());
+        assert_eq!(
+            value["qbittorrent"]["trackers"],
+            serde_yaml::from_str::<Value>("[rarbg.to, nyaa.si]").unwrap()
+        );
+
+        let feed = &value["feeds"][0];
+        assert!(feed.get("matchers").is_none());
+        assert_eq!(feed["matcher"].as_sequence().unwrap().len(), 2);
+
+        // running it again finds nothing left to move
+        assert!(canonicalize(&mut value).is_empty());
+    }
+
+    #[test]
+    fn trackers_are_added_to_existing_ones() {
+        let mut value: Value = serde_yaml::from_str(
+            "
+qbittorrent:
+  trackers: [nyaa.si]
+trackers_to_keep: [rarbg.to, nyaa.si]
+",
+        )
+        .unwrap();
+        canonicalize(&mut value);
+
+        assert_eq!(
+            value["qbittorrent"]["trackers"],
+            serde_yaml::from_str::<Value>("[nyaa.si, rarbg.to]").unwrap()
+        );
+    }
+
+    #[test]
+    fn anchored_matchers_are_named() {
+        let mut value: Value = serde_yaml::from_str(LEGACY).unwrap();
+        canonicalize(&mut value);
+        let changes = name_anchored_matchers(&mut value);
+
+        assert_eq!(changes.len(), 2);
+        assert!(value.get("example_matcher").is_none());
+        assert!(value.get("example_matcher2").is_none());
+        assert!(value["matchers"].get("example_matcher").is_some());
+        assert!(value["matchers"].get("example_matcher2").is_some());
+
+        let names = |feed: &Value| {
+            feed["matcher"]
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .map(|x| x.as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&value["feeds"][0]),
+            vec!["example_matcher", "example_matcher2"]
+        );
+        assert_eq!(names(&value["feeds"][1]), vec!["example_matcher"]);
+    }
+
+    #[test]
+    fn matchers_that_are_not_used_or_clash_stay_put() {
+        let mut value: Value = serde_yaml::from_str(
+            "
+unused:
+  save_folder: /unused
+shows: &shows
+  save_folder: /shows
+matchers:
+  shows:
+    save_folder: /other
+feeds:
+  - url: https://example.com/rss
+    matcher:
+      - *shows
+",
+        )
+        .unwrap();
+
+        assert!(name_anchored_matchers(&mut value).is_empty());
+        assert!(value.get("unused").is_some());
+        assert_eq!(value["feeds"][0]["matcher"][0]["save_folder"], "/shows");
+    }
+
+    #[test]
+    fn migrated_file_still_loads() {
+        let dir = Dir::new("loads", LEGACY);
+        let path = dir.config();
+        let before = FeedManager::from_yaml(&[path.to_str().unwrap()]).unwrap();
+
+        let migration = migrate_file(&path).unwrap();
+        assert_eq!(migration.changes.len(), 4);
+        assert_eq!(
+            std::fs::read_to_string(migration.backup.unwrap()).unwrap(),
+            LEGACY
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("trackers_to_keep"));
+        let after = FeedManager::from_yaml(&[path.to_str().unwrap()]).unwrap();
+        assert_eq!(before.summary(), "2 feeds, 0 named matchers");
+        assert_eq!(after.summary(), "2 feeds, 2 named matchers");
+
+        // nothing is left to do, so there is nothing to back up
+        let migration = migrate_file(&path).unwrap();
+        assert!(migration.changes.is_empty());
+        assert!(migration.backup.is_none());
+    }
+
+    #[test]
+    fn earlier_backups_are_kept() {
+        let dir = Dir::new("backups", LEGACY);
+        let path = dir.config();
+        std::fs::write(dir.0.join("config.yaml.bak"), "earlier").unwrap();
+
+        let backup = migrate_file(&path).unwrap().backup.unwrap();
+        assert_eq!(backup, dir.0.join("config.yaml.bak.1"));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), LEGACY);
+        assert_eq!(
+            std::fs::read_to_string(dir.0.join("config.yaml.bak")).unwrap(),
+            "earlier"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//!
+//! local copy of qbittorrent's torrent list, kept up to date with the incremental
+//! `sync/maindata` endpoint
+//!
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// one response from `sync/maindata`. torrents only carry the fields that changed
+/// since the `rid` that was asked for
+#[derive(Debug, Deserialize)]
+pub struct MainData {
+    pub rid: u64,
+    #[serde(default)]
+    pub full_update: bool,
+    #[serde(default)]
+    pub torrents: HashMap<String, TorrentUpdate>,
+    #[serde(default)]
+    pub torrents_removed: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TorrentUpdate {
+    pub name: Option<String>,
+    pub category: Option<String>,
+    pub tags: Option<String>,
+    pub progress: Option<f64>,
+    pub state: Option<String>,
+    // the tracker currently working for the torrent
+    pub tracker: Option<String>,
+    pub trackers_count: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MirrorTorrent {
+    pub name: String,
+    pub category: String,
+    // comma separated, as qbittorrent sends them
+    pub tags: String,
+    pub progress: f64,
+    pub state: String,
+    pub tracker: String,
+    pub trackers_count: u32,
+}
+
+impl MirrorTorrent {
+    fn apply(&mut self, update: TorrentUpdate) {
+        let TorrentUpdate {
+            name,
+            category,
+            tags,
+            progress,
+            state,
+            tracker,
+            trackers_count,
+        } = update;
+
+        if let Some(name) = name {
+            self.name = name;
+        }
+        if let Some(category) = category {
+            self.category = category;
+        }
+        if let Some(tags) = tags {
+            self.tags = tags;
+        }
+        if let Some(progress) = progress {
+            self.progress = progress;
+        }
+        if let Some(state) = state {
+            self.state = state;
+        }
+        if let Some(tracker) = tracker {
+            self.tracker = tracker;
+        }
+        if let Some(trackers_count) = trackers_count {
+            self.trackers_count = trackers_count;
+        }
+    }
+
+    pub fn completed(&self) -> bool {
+        self.progress >= 1.0
+    }
+
+    /// running or waiting to run, as opposed to paused, stopped or broken
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.state.as_str(),
+            "uploading"
+                | "stalledUP"
+                | "queuedUP"
+                | "forcedUP"
+                | "downloading"
+                | "stalledDL"
+                | "queuedDL"
+                | "forcedDL"
+                | "metaDL"
+                | "forcedMetaDL"
+        )
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.split(',').any(|x| x.trim() == tag)
+    }
+
+    fn same_trackers(&self, other: &MirrorTorrent) -> bool {
+        self.tracker == other.tracker && self.trackers_count == other.trackers_count
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Mirror {
+    // last response id, sent back so qbittorrent only returns what changed
+    rid: u64,
+    torrents: HashMap<String, MirrorTorrent>,
+    // tracker urls of each torrent, fetched separately and only when they may have changed
+    trackers: HashMap<String, Vec<String>>,
+}
+
+impl Mirror {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rid(&self) -> u64 {
+        self.rid
+    }
+
+    /// merge a `sync/maindata` response into the mirror
+    pub fn apply(&mut self, data: MainData) {
+        if data.full_update {
+            // start over, but keep the trackers of torrents whose trackers look the same
+            let previous = std::mem::take(&mut self.torrents);
+
+            for (hash, update) in data.torrents {
+                let mut torrent = MirrorTorrent::default();
+                torrent.apply(update);
+
+                let unchanged = previous
+                    .get(&hash)
+                    .map(|x| x.same_trackers(&torrent))
+                    .unwrap_or(false);
+                if !unchanged {
+                    self.trackers.remove(&hash);
+                }
+
+                self.torrents.insert(hash, torrent);
+            }
+
+            let torrents = &self.torrents;
+            self.trackers.retain(|hash, _| torrents.contains_key(hash));
+        } else {
+            for (hash, update) in data.torrents {
+                let torrent = self.torrents.entry(hash.clone()).or_default();
+                let before = torrent.clone();
+                torrent.apply(update);
+
+                if !before.same_trackers(torrent) {
+                    self.trackers.remove(&hash);
+                }
+            }
+
+            for hash in data.torrents_removed {
+                self.torrents.remove(&hash);
+                self.trackers.remove(&hash);
+            }
+        }
+
+        self.rid = data.rid;
+    }
+
+    pub fn torrents(&self) -> impl Iterator<Item = (&String, &MirrorTorrent)> {
+        self.torrents.iter()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&MirrorTorrent> {
+        self.torrents.get(hash)
+    }
+
+    /// tracker urls of a torrent, if they are known and have not changed since
+    pub fn trackers(&self, hash: &str) -> Option<&[String]> {
+        self.trackers.get(hash).map(|x| x.as_slice())
+    }
+
+    pub fn set_trackers(&mut self, hash: &str, trackers: Vec<String>) {
+        if self.torrents.contains_key(hash) {
+            self.trackers.insert(hash.to_string(), trackers);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn main_data(json: &str) -> MainData {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn full() -> MainData {
+        main_data(
+            r#"{
+                "rid": 1,
+                "full_update": true,
+                "torrents": {
+                    "aaa": {"name": "Show S01E01", "category": "tv", "tags": "autodl, x",
+                            "progress": 0.5, "state": "downloading",
+                            "tracker": "https://tracker.example/announce", "trackers_count": 1},
+                    "bbb": {"name": "Movie", "progress": 1.0, "state": "pausedUP",
+                            "tracker": "udp://open.example:1337", "trackers_count": 3}
+                }
+            }"#,
+        )
+    }
+
+    #[test]
+    fn full_update_fills_the_mirror() {
+        let mut mirror = Mirror::new();
+        mirror.apply(full());
+
+        assert_eq!(mirror.rid(), 1);
+        assert_eq!(mirror.torrents().count(), 2);
+
+        let show = mirror.get("aaa").unwrap();
+        assert_eq!(show.name, "Show S01E01");
+        assert!(show.has_tag("x") && show.has_tag("autodl"));
+        assert!(show.is_active() && !show.completed());
+
+        let movie = mirror.get("bbb").unwrap();
+        assert!(!movie.is_active() && movie.completed());
+        assert_eq!(movie.category, "");
+    }
+
+    #[test]
+    fn deltas_only_change_what_they_carry() {
+        let mut mirror = Mirror::new();
+        mirror.apply(full());
+        mirror.apply(main_data(
+            r#"{
+                "rid": 2,
+                "torrents": {
+                    "aaa": {"progress": 1.0, "state": "uploading"},
+                    "ccc": {"name": "New", "state": "metaDL"}
+                },
+                "torrents_removed": ["bbb"]
+            }"#,
+        ));
+
+        assert_eq!(mirror.rid(), 2);
+        let show = mirror.get("aaa").unwrap();
+        assert_eq!(show.name, "Show S01E01");
+        assert_eq!(show.category, "tv");
+        assert_eq!(show.state, "uploading");
+        assert!(show.completed());
+
+        assert_eq!(mirror.get("ccc").unwrap().name, "New");
+        assert!(mirror.get("bbb").is_none());
+    }
+
+    #[test]
+    fn trackers_are_kept_until_they_may_have_changed() {
+        let mut mirror = Mirror::new();
+        mirror.apply(full());
+        mirror.set_trackers("aaa", vec!["https://tracker.example/announce".to_string()]);
+        mirror.set_trackers("bbb", vec!["udp://open.example:1337".to_string()]);
+        // unknown torrents are ignored
+        mirror.set_trackers("zzz", vec![]);
+        assert!(mirror.trackers("zzz").is_none());
+
+        // a delta without tracker fields keeps them
+        mirror.apply(main_data(
+            r#"{"rid": 2, "torrents": {"aaa": {"progress": 0.7}}}"#,
+        ));
+        assert!(mirror.trackers("aaa").is_some());
+
+        // a different working tracker drops them, as does removing the torrent
+        mirror.apply(main_data(
+            r#"{"rid": 3, "torrents": {"aaa": {"tracker": "https://other.example/announce"}},
+                "torrents_removed": ["bbb"]}"#,
+        ));
+        assert!(mirror.trackers("aaa").is_none());
+        assert!(mirror.trackers("bbb").is_none());
+    }
+
+    #[test]
+    fn full_update_replaces_everything() {
+        let mut mirror = Mirror::new();
+        mirror.apply(full());
+        mirror.set_trackers("aaa", vec!["https://tracker.example/announce".to_string()]);
+        mirror.set_trackers("bbb", vec!["udp://open.example:1337".to_string()]);
+
+        // qbittorrent starts over when the rid is unknown to it: torrents missing from
+        // the response are gone, and fields missing from it are empty
+        mirror.apply(main_data(
+            r#"{
+                "rid": 7,
+                "full_update": true,
+                "torrents": {
+                    "aaa": {"name": "Show S01E01", "tracker": "https://tracker.example/announce",
+                            "trackers_count": 1}
+                }
+            }"#,
+        ));
+
+        assert_eq!(mirror.rid(), 7);
+        assert_eq!(mirror.torrents().count(), 1);
+        assert_eq!(mirror.get("aaa").unwrap().category, "");
+        // same trackers as before, so they did not have to be fetched again
+        assert!(mirror.trackers("aaa").is_some());
+        assert!(mirror.trackers("bbb").is_none());
+    }
+}
@@ -1,40 +1,58 @@
+use super::backoff::{FeedHealth, HealthState, RetryConfig};
+use super::client::HostConnections;
+use super::control::{FeedCommand, FeedCommands, ItemState, ItemView};
+use super::events::{Event, EventSender};
+use super::history::{History, Outcome};
+use super::metrics::Metrics;
+use super::mirror::{Mirror, MirrorTorrent};
+use super::qbit_api::{AddTorrent, QbitApi};
+use super::ratelimit::RateLimiter;
 use super::rss;
-use super::yaml::{QbittorrentAuthentication, RssFeed};
-use super::Error;
-use qbittorrent::{self, traits::*};
-use std::collections::HashSet;
+use super::status::Status;
+use super::supervisor::Shutdown;
+use super::torrents::{ExemptReason, PauseReason, TorrentTable};
+use super::yaml::{QbittorrentAuthentication, RssFeed, TorrentMatch};
+use super::{Context, Error};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::ops::Deref;
 use std::sync::Arc;
 
 use reqwest;
 
-const AUTODL_CATEGORY: &str = "AUTO_DL";
-const TITLE_BAN_CATEGORY: &str = "TITLE_BAN";
+// every torrent we add carries this tag
+pub(crate) const AUTODL_TAG: &str = "autodl";
+pub(crate) const BANNED_TITLE_TAG: &str = "autodl:banned-title";
+// torrents added before tags were used only have this category to go by
+const LEGACY_CATEGORY: &str = "AUTO_DL";
 
 #[derive(Debug)]
 pub struct QbitMonitor {
-    pub api: Arc<qbittorrent::api::Api>,
-    // checked_hashes: HashSet<String>,
-    all_hashes: HashSet<String>,
-    // paused due to tracker requirements
-    paused_tracker_hashes: HashSet<*const String>,
-    // paused do to title issues
-    paused_title_hashes: HashSet<*const String>,
+    pub qbit_api: Arc<QbitApi>,
+    // every torrent in qbittorrent and whether we paused it, shared with the web ui
+    // so pauses can be undone
+    pub torrents: Arc<TorrentTable>,
+    // local copy of qbittorrent's torrent list
+    mirror: Mirror,
     trackers: Vec<String>,
+    exempt_category: String,
+    exempt_tag: String,
     title_bans: Vec<String>,
     #[allow(dead_code)]
     file_bans: Vec<String>,
+    events: EventSender,
+    metrics: Arc<Metrics>,
 }
 
 impl QbitMonitor {
-    pub async fn new(qbit_auth: QbittorrentAuthentication) -> Result<Self, Error> {
-        let api: qbittorrent::Api =
-            qbittorrent::Api::new(&qbit_auth.username, &qbit_auth.password, &qbit_auth.address)
-                .await?;
+    pub async fn new(
+        qbit_auth: QbittorrentAuthentication,
+        events: EventSender,
+        metrics: Arc<Metrics>,
+        state_path: Option<std::path::PathBuf>,
+    ) -> Result<Self, Error> {
+        let torrents = TorrentTable::load(state_path)?;
 
-        // set up category for torrents that do not meet title criteria
-        api.add_category(TITLE_BAN_CATEGORY, "").await?;
+        let qbit_api = QbitApi::new(&qbit_auth).await?;
 
         let title_bans = qbit_auth.title_bans.unwrap_or_default();
         let file_bans = qbit_auth.file_bans.unwrap_or_default();
@@ -43,91 +61,162 @@ impl QbitMonitor {
         let title_bans = lower(title_bans);
         let file_bans = lower(file_bans);
         let trackers = lower(qbit_auth.trackers);
+        let exempt_category = qbit_auth.exempt_category;
+        let exempt_tag = qbit_auth.exempt_tag;
 
         Ok(Self {
-            api: Arc::new(api),
-            all_hashes: HashSet::new(),
-            paused_tracker_hashes: HashSet::new(),
-            paused_title_hashes: HashSet::new(),
+            qbit_api: Arc::new(qbit_api),
+            torrents: Arc::new(torrents),
+            mirror: Mirror::new(),
             trackers,
+            exempt_category,
+            exempt_tag,
             title_bans,
             file_bans,
+            events,
+            metrics,
         })
     }
 
-    pub async fn sync_qbit(&mut self) -> Result<(), Error> {
-        let all_torrents: Vec<qbittorrent::data::Torrent> =
-            qbittorrent::queries::TorrentRequestBuilder::default()
-                .build()
-                .expect("torrent request builder error")
-                .send(&self.api)
-                .await?;
+    /// check qbittorrent every minute until shutdown
+    pub async fn run(&mut self, status: &Status, shutdown: &Shutdown) {
+        while !shutdown.is_triggered() {
+            println! {"looping through qbittorrent checks"};
 
-        all_torrents.iter().for_each(|new_torrent| {
-            self.all_hashes
-                .insert(new_torrent.hash().deref().to_string());
-        });
+            let mut errors = Vec::new();
 
-        Ok(())
+            // get a list of all hashes
+            let synced = self.sync_qbit().await;
+            status.qbit_synced(synced.as_ref().err());
+            if let Err(e) = synced {
+                println! {"error getting full torrent list hashes: {}", e}
+                errors.push(e.to_string());
+            }
+
+            // stop touching torrents that were resumed by hand
+            self.detect_resumes();
+
+            // pause all torrents from trackers not matching
+            if let Err(e) = self.pause_all().await {
+                println! {"there was an error pausing all public torrents: {}", e}
+                errors.push(e.to_string());
+            }
+
+            // pause all torrents with titles we do not want
+            if let Err(e) = self.check_titles().await {
+                println! {"there was an error checking torrent titles: {}", e}
+                errors.push(e.to_string());
+            }
+
+            if let Err(e) = self.torrents.save() {
+                println! {"could not save torrent states: {}", e}
+                errors.push(e.to_string());
+            }
+
+            status.qbit_checked(errors);
+
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(60)) => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+
+        println! {"stopped qbittorrent checks"};
     }
 
-    pub async fn pause_all(&mut self) -> Result<(), Error> {
-        let all_torrents: Vec<qbittorrent::data::Torrent> =
-            qbittorrent::queries::TorrentRequestBuilder::default()
-                .filter(qbittorrent::queries::TorrentFilter::Completed)
-                .build()
-                .expect("torrent request builder error")
-                .send(&self.api)
-                .await?;
+    pub async fn sync_qbit(&mut self) -> Result<(), Error> {
+        // only what changed since the last response is sent back
+        let timer = self.metrics.qbit_timer("sync/maindata");
+        let data = self.qbit_api.maindata(self.mirror.rid()).await?;
+        timer.observe_duration();
 
-        let api = &self.api;
+        self.mirror.apply(data);
+        self.torrents.sync(
+            self.mirror
+                .torrents()
+                .map(|(hash, x)| (hash.as_str(), x.name.as_str())),
+        );
 
-        for torrent in all_torrents {
-            // get a pointer to some item in the hashset
-            let ptr = if let Some(hash) = self.all_hashes.get(torrent.hash().deref()) {
-                hash as *const String
-            } else {
-                println! {"hash was not in all hashes as expected for torrent: {}", torrent.name()}
-                continue;
+        Ok(())
+    }
+
+    /// exempt torrents that we paused and someone resumed since
+    pub fn detect_resumes(&mut self) {
+        for hash in self.torrents.paused_hashes() {
+            let torrent = match self.mirror.get(&hash) {
+                Some(torrent) if torrent.is_active() => torrent,
+                _ => continue,
             };
 
-            // if we have the pointer stored then we have paused the torrent previously
-            if self.paused_tracker_hashes.contains(&ptr) {
-                continue;
+            println! {"torrent was resumed by hand and will not be paused again: {}", torrent.name};
+            if let Err(e) = self.torrents.exempt(&hash, ExemptReason::ResumedByHand) {
+                println! {"could not exempt torrent {}: {}", torrent.name, e};
             }
+        }
+    }
 
-            // get all trackers attached to this torrent
-            let tracker = match torrent.trackers(api).await {
-                Ok(x) => x,
-                Err(e) => {
-                    println! {"error getting trackers for torrent {}", torrent.name()};
-                    dbg! {e};
-                    continue;
+    // exempt torrents are never paused, whether by state or by category / tag
+    fn is_exempt(&self, hash: &str, torrent: &MirrorTorrent) -> bool {
+        self.torrents.is_exempt(hash)
+            || torrent.category == self.exempt_category
+            || torrent.has_tag(&self.exempt_tag)
+    }
+
+    pub async fn pause_all(&mut self) -> Result<(), Error> {
+        // completed torrents that we have not paused before
+        let candidates = self
+            .mirror
+            .torrents()
+            .filter(|(_, x)| x.completed())
+            .filter(|(hash, x)| !self.is_exempt(hash, x))
+            .filter(|(hash, _)| !self.torrents.paused_for(hash, PauseReason::Tracker))
+            .map(|(hash, x)| (hash.clone(), x.name.clone()))
+            .collect::<Vec<_>>();
+
+        for (hash, name) in candidates {
+            // trackers are only fetched for torrents that are new or whose trackers changed
+            let trackers = match self.mirror.trackers(&hash) {
+                Some(trackers) => trackers.to_vec(),
+                None => {
+                    let timer = self.metrics.qbit_timer("torrents/trackers");
+                    let trackers = self.qbit_api.trackers(&hash).await;
+                    timer.observe_duration();
+
+                    match trackers {
+                        Ok(trackers) => {
+                            self.mirror.set_trackers(&hash, trackers.clone());
+                            trackers
+                        }
+                        Err(e) => {
+                            println! {"error getting trackers for torrent {}: {}", name, e};
+                            continue;
+                        }
+                    }
                 }
             };
 
-            let mut pause_torrent = true;
             // check each tracker for the torrent against the user-provided list of ok-trackers
-            for tracker in tracker {
-                // if we we need to seed this tracker then skip tracker
-                if self.keep_seeding_tracker(&tracker) {
-                    pause_torrent = false;
-                }
+            if trackers.iter().any(|x| self.keep_seeding_tracker(x)) {
+                continue;
             }
 
-            // if we never found a tracker that we allowed then pause_torrent is true
-            // and we send the command to stop seeding
-            if pause_torrent {
-                // if we get here then we know none of the trackers are ones we care about
-                match torrent.pause(api).await {
-                    // the torrent has been successfully paused
-                    Ok(_) => {
-                        self.paused_tracker_hashes.insert(ptr);
-                    }
-                    Err(e) => {
-                        println! {"error pausing torrent for tracker reasons: {} ", torrent.name()}
-                        dbg! {e};
-                    }
+            // if we get here then we know none of the trackers are ones we care about
+            let timer = self.metrics.qbit_timer("torrents/pause");
+            let paused = self.qbit_api.pause(&hash).await;
+            timer.observe_duration();
+
+            match paused {
+                // the torrent has been successfully paused
+                Ok(_) => {
+                    self.torrents.mark_paused(&hash, PauseReason::Tracker);
+                    self.metrics
+                        .torrents_paused
+                        .with_label_values(&["tracker"])
+                        .inc();
+                    self.events.send(Event::Paused { name, hash });
+                }
+                Err(e) => {
+                    println! {"error pausing torrent for tracker reasons: {}: {}", name, e}
                 }
             }
         }
@@ -141,46 +230,42 @@ impl QbitMonitor {
             return Ok(());
         }
 
-        // fetch all torrents that have been automatically downloaded
-        let all_torrents: Vec<qbittorrent::data::Torrent> =
-            qbittorrent::queries::TorrentRequestBuilder::default()
-                .filter(qbittorrent::queries::TorrentFilter::All)
-                .category(AUTODL_CATEGORY)
-                .build()
-                .expect("torrent request builder error")
-                .send(&self.api)
-                .await?;
-
-        for torrent in all_torrents {
-            // println! {"testing to pause torrent name: {}", torrent.name()};
+        // automatically downloaded torrents with a banned title that we have not paused before
+        let candidates = self
+            .mirror
+            .torrents()
+            .filter(|(_, x)| x.has_tag(AUTODL_TAG) || x.category == LEGACY_CATEGORY)
+            .filter(|(hash, x)| !self.is_exempt(hash, x))
+            .filter(|(hash, _)| !self.torrents.paused_for(hash, PauseReason::Title))
+            .filter(|(_, x)| !self.torrent_title_acceptable(&x.name))
+            .map(|(hash, x)| (hash.clone(), x.name.clone()))
+            .collect::<Vec<_>>();
 
-            // get a pointer to some item in the hashset
-            let ptr = if let Some(hash) = self.all_hashes.get(torrent.hash().deref()) {
-                hash as *const String
-            } else {
-                println! {"hash was not in all hashes as expected for torrent: {}", torrent.name()}
-                continue;
-            };
+        for (hash, name) in candidates {
+            let timer = self.metrics.qbit_timer("torrents/addTags");
+            let tagged = self.qbit_api.add_tags(&hash, BANNED_TITLE_TAG).await;
+            timer.observe_duration();
 
-            // if we have the pointer stored then we have paused the torrent previously
-            if self.paused_title_hashes.contains(&ptr) {
+            if let Err(e) = tagged {
+                println! {"error tagging torrent with a banned title: {}: {}", name, e}
                 continue;
             }
 
-            // check if the title is acceptable
-            if !self.torrent_title_acceptable(&torrent) {
-                // if we get here then we know we need to pause things
-                match torrent.set_category(&self.api, TITLE_BAN_CATEGORY).await {
-                    // the torrent has been successfully paused
-                    Ok(_) => {
-                        if torrent.pause(&self.api).await.is_ok() {
-                            self.paused_title_hashes.insert(ptr);
-                        }
-                    }
-                    Err(e) => {
-                        println! {"error setting title ban category for torrent: {} ", torrent.name()}
-                        dbg! {e};
-                    }
+            let timer = self.metrics.qbit_timer("torrents/pause");
+            let paused = self.qbit_api.pause(&hash).await;
+            timer.observe_duration();
+
+            match paused {
+                Ok(_) => {
+                    self.torrents.mark_paused(&hash, PauseReason::Title);
+                    self.metrics
+                        .torrents_paused
+                        .with_label_values(&["title"])
+                        .inc();
+                    self.events.send(Event::TitleBanned { name, hash });
+                }
+                Err(e) => {
+                    println! {"error pausing torrent with a banned title: {}: {}", name, e}
                 }
             }
         }
@@ -188,9 +273,9 @@ impl QbitMonitor {
         Ok(())
     }
 
-    fn keep_seeding_tracker(&self, t_data: &qbittorrent::data::Tracker) -> bool {
+    fn keep_seeding_tracker(&self, url: &str) -> bool {
         for i in &self.trackers {
-            if t_data.url().contains(i) {
+            if url.contains(i) {
                 return true;
             }
         }
@@ -198,9 +283,10 @@ impl QbitMonitor {
         false
     }
 
-    fn torrent_title_acceptable(&self, t_data: &qbittorrent::data::Torrent) -> bool {
+    fn torrent_title_acceptable(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
         for i in &self.title_bans {
-            if t_data.name().to_ascii_lowercase().contains(i) {
+            if name.contains(i) {
                 return false;
             }
         }
@@ -212,41 +298,433 @@ impl QbitMonitor {
 #[derive(Debug)]
 pub struct FeedMonitor {
     client: reqwest::Client,
-    // rss hashes that we have looked at
-    previous_hashes: HashSet<u64>,
+    // rss hashes that we have looked at, and the downloads that failed
+    downloads: Downloads,
     feed: RssFeed,
-    qbit: Arc<qbittorrent::api::Api>,
+    // etag / last-modified of the last fetch
+    cache: rss::FeedCache,
+    // items of the last fetch that returned the feed, for the web ui
+    items: Vec<rss::SerdeTorrentData>,
+    handles: FeedHandles,
+    // when the feed goes through a proxy or needs credentials we download .torrent files
+    // ourselves, since qbittorrent would fetch them over its own plain connection
+    fetch_torrents: bool,
+    start_delay: std::time::Duration,
+    health: FeedHealth,
+}
+
+#[derive(Debug, Default)]
+struct Downloads {
+    previous_hashes: HashSet<u64>,
+    // downloads that failed, kept so they can be retried by hand
+    failed: HashMap<u64, (rss::SerdeTorrentData, TorrentMatch)>,
+}
+
+impl Downloads {
+    // remember the outcome of a download in the history and for later retries
+    fn record(
+        &mut self,
+        history: &History,
+        feed: &str,
+        data: &rss::TorrentData<'_>,
+        result: &Result<(), Error>,
+    ) {
+        let outcome = match result {
+            Ok(()) => {
+                self.previous_hashes.insert(data.item_hash);
+                self.failed.remove(&data.item_hash);
+                Outcome::Downloaded
+            }
+            Err(e) => {
+                self.failed.insert(
+                    data.item_hash,
+                    (data.detach(), data.original_matcher.clone()),
+                );
+                Outcome::Failed {
+                    error: e.to_string(),
+                }
+            }
+        };
+
+        history.record(
+            feed,
+            &data.title,
+            &data.original_matcher.name,
+            data.item_hash,
+            outcome,
+        );
+    }
+}
+
+// `autodl:<key>=<value>`. qbittorrent splits tags on commas, so they cannot be in a tag
+fn tag(key: &str, value: &str) -> String {
+    format! {"autodl:{}={}", key, value.replace(',', "_")}
+}
+
+/// everything a feed task shares with the rest of the program
+#[derive(Debug, Clone)]
+pub struct FeedHandles {
+    pub qbit_api: Arc<QbitApi>,
+    pub limiter: Arc<RateLimiter>,
+    pub connections: Arc<HostConnections>,
+    pub events: EventSender,
+    pub metrics: Arc<Metrics>,
+    pub status: Arc<Status>,
+    pub history: Arc<History>,
+    pub matcher_names: Arc<MatcherNames>,
 }
+
+/// names of the matchers of every feed, which have to stay unique across the whole
+/// config as matchers are added, replaced and removed through the api
+#[derive(Debug, Default)]
+pub struct MatcherNames {
+    // the top level `matchers`, whose names stay taken for as long as the config does
+    named: HashSet<String>,
+    taken: std::sync::Mutex<HashSet<String>>,
+}
+
+impl MatcherNames {
+    pub fn new(named: HashSet<String>, taken: HashSet<String>) -> Self {
+        let taken = std::sync::Mutex::new(taken.union(&named).cloned().collect());
+        Self { named, taken }
+    }
+
+    // name `matcher`, which replaces the matcher named `replaced`, if it has no name.
+    // a name that was given and belongs to another matcher is an error
+    fn claim(
+        &self,
+        matcher: &mut TorrentMatch,
+        index: usize,
+        replaced: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut taken = self.taken.lock().expect("matcher names lock poisoned");
+        let released = replaced.filter(|x| !self.named.contains(*x) && taken.remove(*x));
+
+        if !matcher.name.is_empty() && taken.contains(&matcher.name) {
+            if let Some(released) = released {
+                taken.insert(released.to_string());
+            }
+            return Err(Error::DuplicateName {
+                kind: "matcher",
+                name: matcher.name.clone(),
+            });
+        }
+
+        matcher.fill_name(index, &mut taken);
+        taken.insert(matcher.name.clone());
+        Ok(())
+    }
+
+    fn release(&self, name: &str) {
+        if !self.named.contains(name) {
+            self.taken
+                .lock()
+                .expect("matcher names lock poisoned")
+                .remove(name);
+        }
+    }
+}
+
 impl FeedMonitor {
-    pub fn from_feed(data: RssFeed, qbit: Arc<qbittorrent::api::Api>) -> Self {
+    pub fn from_feed(
+        data: RssFeed,
+        client: reqwest::Client,
+        fetch_torrents: bool,
+        retry: RetryConfig,
+        handles: FeedHandles,
+    ) -> Self {
         FeedMonitor {
-            client: reqwest::Client::new(),
-            previous_hashes: HashSet::new(),
+            client,
+            downloads: Downloads::default(),
             feed: data,
-            qbit,
+            cache: rss::FeedCache::default(),
+            items: Vec::new(),
+            handles,
+            fetch_torrents,
+            start_delay: std::time::Duration::from_secs(0),
+            health: FeedHealth::new(retry),
         }
     }
+
+    /// poll the feed until shutdown, handling commands from its [`FeedControl`] in
+    /// between. a poll that has started is always finished
+    ///
+    /// [`FeedControl`]: super::control::FeedControl
+    pub async fn run(&mut self, commands: &mut FeedCommands, shutdown: &Shutdown) {
+        let mut next = tokio::time::Instant::now() + self.start_delay;
+        // a task restarted after a panic polls again right away
+        self.start_delay = std::time::Duration::from_secs(0);
+        let mut listening = true;
+
+        while !shutdown.is_triggered() {
+            tokio::select! {
+                _ = tokio::time::sleep_until(next) => {
+                    let wait = if commands.paused.load(std::sync::atomic::Ordering::Relaxed) {
+                        let wait = std::time::Duration::from_secs(self.feed.update_interval as u64);
+                        self.handles.status.feed_paused(&self.feed.name, wait);
+                        wait
+                    } else {
+                        self.poll().await
+                    };
+                    next = tokio::time::Instant::now() + wait;
+                }
+                command = commands.receiver.recv(), if listening => match command {
+                    Some(FeedCommand::Poll(reply)) => {
+                        let wait = self.poll().await;
+                        next = tokio::time::Instant::now() + wait;
+                        let _ = reply.send(Ok(()));
+                    }
+                    Some(command) => self.handle_command(command).await,
+                    // nobody can reach this task anymore, just keep polling
+                    None => listening = false,
+                },
+                _ = shutdown.wait() => {}
+            }
+        }
+
+        println! {"stopped polling {}", self.feed.name};
+    }
+
+    async fn handle_command(&mut self, command: FeedCommand) {
+        let missing = |index| Error::NotFound(format! {"matcher {}", index});
+
+        match command {
+            // `run` answers polls itself, so one ending up here has nothing left to do
+            FeedCommand::Poll(reply) => {
+                println! {"ignoring a poll of {} outside of its loop", self.feed.name};
+                let _ = reply.send(Ok(()));
+            }
+            FeedCommand::ListMatchers(reply) => {
+                let _ = reply.send(Ok(self.feed.matcher.clone()));
+            }
+            FeedCommand::AddMatcher(mut matcher, reply) => {
+                matcher.lowercase();
+                let index = self.feed.matcher.len();
+                let result = self
+                    .handles
+                    .matcher_names
+                    .claim(&mut matcher, index, None)
+                    .map(|_| {
+                        self.feed.matcher.push(matcher);
+                        index
+                    });
+                let _ = reply.send(result);
+            }
+            FeedCommand::UpdateMatcher(index, mut matcher, reply) => {
+                let names = &self.handles.matcher_names;
+                let result = match self.feed.matcher.get_mut(index) {
+                    Some(existing) => {
+                        matcher.lowercase();
+                        names
+                            .claim(&mut matcher, index, Some(&existing.name))
+                            .map(|_| *existing = matcher)
+                    }
+                    None => Err(missing(index)),
+                };
+                let _ = reply.send(result);
+            }
+            FeedCommand::RemoveMatcher(index, reply) => {
+                let result = if index < self.feed.matcher.len() {
+                    let matcher = self.feed.matcher.remove(index);
+                    self.handles.matcher_names.release(&matcher.name);
+                    Ok(matcher)
+                } else {
+                    Err(missing(index))
+                };
+                let _ = reply.send(result);
+            }
+            FeedCommand::Retry(item_hash, reply) => {
+                let _ = reply.send(self.retry(item_hash).await);
+            }
+            FeedCommand::Items(reply) => {
+                let _ = reply.send(Ok(self.item_views(&self.feed.matcher)));
+            }
+            FeedCommand::Preview(mut matcher, reply) => {
+                matcher.lowercase();
+                let _ = reply.send(Ok(self.item_views(std::slice::from_ref(&matcher))));
+            }
+        }
+    }
+
+    // the cached items of the feed, as seen by `matchers`
+    fn item_views(&self, matchers: &[TorrentMatch]) -> Vec<ItemView> {
+        self.items
+            .iter()
+            .map(|item| {
+                let matcher = matchers.iter().position(|x| x.matches(item));
+
+                let state = if self.downloads.previous_hashes.contains(&item.item_hash) {
+                    ItemState::Downloaded
+                } else if self.downloads.failed.contains_key(&item.item_hash) {
+                    ItemState::Failed
+                } else if matcher.is_some() {
+                    ItemState::Matched
+                } else {
+                    ItemState::Unmatched
+                };
+
+                let mut tags = item.tags.iter().cloned().collect::<Vec<_>>();
+                tags.sort();
+
+                ItemView {
+                    title: item.title.clone(),
+                    tags,
+                    size: item.size,
+                    item_hash: item.item_hash,
+                    matcher,
+                    state,
+                }
+            })
+            .collect()
+    }
+
+    // download a previously failed item again
+    async fn retry(&mut self, item_hash: u64) -> Result<(), Error> {
+        let (item, matcher) = match self.downloads.failed.remove(&item_hash) {
+            Some(failed) => failed,
+            None => return Err(Error::NotFound(format! {"failed item {}", item_hash})),
+        };
+
+        let data = rss::TorrentData::from_serde_data(item, &matcher);
+        let result = self.start_qbit_download(&data).await;
+        self.downloads
+            .record(&self.handles.history, &self.feed.name, &data, &result);
+
+        result
+    }
+
+    pub fn with_start_delay(mut self, start_delay: std::time::Duration) -> Self {
+        self.start_delay = start_delay;
+        self
+    }
+
+    // how long to wait before the first poll
+    pub fn start_delay(&self) -> std::time::Duration {
+        self.start_delay
+    }
+
+    // run a single update and work out how long to wait before the next one
+    pub async fn poll(&mut self) -> std::time::Duration {
+        let wait = self.poll_inner().await;
+        self.handles
+            .status
+            .update_feed(&self.feed.name, &self.health, wait);
+        wait
+    }
+
+    async fn poll_inner(&mut self) -> std::time::Duration {
+        match self.run_update().await {
+            Ok(countdown) => {
+                self.health.record_success();
+                println! {"Finished RSS update for feed: {}", self.feed.name};
+                std::time::Duration::from_secs(countdown as u64)
+            }
+            Err(e) => {
+                let previous = self.health.state;
+                let mut wait = self.health.record_failure(e.to_string(), e.retry_after());
+
+                // permanent errors will not go away by asking again sooner,
+                // so never retry them faster than the normal interval
+                if !e.is_retryable() {
+                    wait = wait.max(std::time::Duration::from_secs(
+                        self.feed.update_interval as u64,
+                    ));
+                }
+
+                println! {
+                    "error updating feed ({} consecutive failures), retrying in {}s: {}",
+                    self.health.consecutive_failures,
+                    wait.as_secs(),
+                    e
+                };
+
+                if self.health.state == HealthState::Failing && previous != HealthState::Failing {
+                    println! {"FEED FAILING: {} has failed {} times in a row", self.feed.name, self.health.consecutive_failures};
+                    self.handles.events.send(Event::FeedFailing {
+                        feed: self.feed.name.clone(),
+                        failures: self.health.consecutive_failures,
+                        error: e.to_string(),
+                    });
+                }
+
+                wait
+            }
+        }
+    }
+
+    pub fn health(&self) -> &FeedHealth {
+        &self.health
+    }
     // check all rss feeds for updates: update, pull torrents, and download them if possible
     pub async fn run_update(&mut self) -> Result<u32, Error> {
+        let metrics = Arc::clone(&self.handles.metrics);
+        let name = self.feed.name.clone();
+        let feed_label = [name.as_str()];
+        metrics.feed_polls.with_label_values(&feed_label).inc();
+
+        self.handles.limiter.acquire(&self.feed.url).await;
+        let permit = self.handles.connections.acquire(&self.feed.url).await;
+
         // fetch data from the torrent feed. Error out if there was an issue with the request
-        let data = match self.feed.fetch_new(&self.client).await {
+        let fetched = self.feed.fetch_new(&self.client, &self.cache).await;
+        drop(permit);
+        let data = match fetched {
             Ok(data) => data,
-            Err(e) => return Err(e),
+            Err(e) => {
+                metrics.feed_failures.with_label_values(&feed_label).inc();
+                return Err(e);
+            }
         };
 
-        // let mut write = &mut self.previous_hashes;
+        metrics.feed_successes.with_label_values(&feed_label).inc();
+        metrics
+            .items_parsed
+            .with_label_values(&feed_label)
+            .inc_by(data.parsed as u64);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        metrics
+            .last_success
+            .with_label_values(&feed_label)
+            .set(now.as_secs_f64());
 
-        for item in data {
+        if let Some(items) = data.items {
+            self.items = items;
+        }
+
+        let mut all_downloaded = true;
+        for item in data.matched {
             // if we have not previously downloaded the torrent
-            if !self.previous_hashes.contains(&item.item_hash) {
+            if !self.downloads.previous_hashes.contains(&item.item_hash) {
+                metrics
+                    .items_matched
+                    .with_label_values(&[name.as_str(), item.original_matcher.name.as_str()])
+                    .inc();
+
                 // tell the client to download the torrent
-                if self.start_qbit_download(&item).await.is_ok() {
-                    // insert it to the history
-                    // write.insert(item.item_hash);
-                    self.previous_hashes.insert(item.item_hash);
-                } else {
-                    dbg! {"failed to download file:", item.title};
+                let result = self.start_qbit_download(&item).await;
+                match &result {
+                    Ok(_) => {
+                        metrics.downloads_added.with_label_values(&feed_label).inc();
+                    }
+                    Err(e) => {
+                        all_downloaded = false;
+                        println! {"failed to download file: {}", e}
+                    }
                 }
+
+                // insert it to the history
+                self.downloads
+                    .record(&self.handles.history, &self.feed.name, &item, &result);
+            }
+        }
+
+        // a failed download is tried again when the feed is next fetched in full. with
+        // the validators of this fetch the tracker would answer with a 304 instead
+        if let Some(cache) = data.cache {
+            if all_downloaded {
+                self.cache = cache;
             }
         }
 
@@ -259,29 +737,552 @@ impl FeedMonitor {
 
     // start qbittorrnet's download of a file
     pub async fn start_qbit_download(&self, data: &rss::TorrentData<'_>) -> Result<(), Error> {
-        dbg! {"downloading new file"};
+        println! {"downloading {} from {}", data.title, self.feed.name};
 
         let save_folder = data.original_matcher.save_folder.clone();
 
         if let Err(e) = fs::create_dir_all(&save_folder) {
             if e.kind() != std::io::ErrorKind::AlreadyExists {
-                return Err(Error::from(e));
+                return Err(Error::from(e).item(data.title.as_str()));
             }
         }
 
-        let _x = data.write_metadata();
+        if let Err(e) = data.write_metadata() {
+            println! {"could not write metadata for {}: {}", save_folder, e};
+        }
+
+        // qbittorrent fetches the .torrent right away when given the link, so this counts
+        // against the tracker's limit either way
+        if !data.download_link.starts_with("magnet:") {
+            self.handles.limiter.acquire(&data.download_link).await;
+        }
 
-        let req = qbittorrent::queries::TorrentDownloadBuilder::default()
-            .savepath(&save_folder)
-            .urls(&data.download_link)
-            .paused(data.original_matcher.start_condition())
-            .category(AUTODL_CATEGORY)
-            .build()
-            .expect("incorrect building of download builder");
+        let options = AddTorrent {
+            savepath: save_folder,
+            category: data.original_matcher.category.clone(),
+            tags: vec![
+                AUTODL_TAG.to_string(),
+                tag("feed", &self.feed.name),
+                tag("matcher", &data.original_matcher.name),
+            ],
+            paused: data.original_matcher.start_paused.unwrap_or(false),
+        };
 
-        self.qbit.add_new_torrent(&req).await?;
+        let timer = self.handles.metrics.qbit_timer("torrents/add");
+        // magnet links have nothing to download, so qbittorrent can always take those
+        if self.fetch_torrents && !data.download_link.starts_with("magnet:") {
+            let torrent = self
+                .fetch_torrent(data)
+                .await
+                .item_context(data.title.as_str())?;
+
+            self.handles
+                .qbit_api
+                .add_torrent_file(&data.title, torrent, &options)
+                .await?;
+        } else {
+            self.handles
+                .qbit_api
+                .add_torrent_urls(&data.download_link, &options)
+                .await
+                .item_context(data.title.as_str())?;
+        }
+        timer.observe_duration();
 
         println! {"successfully downloaded new torrent: {}", data.title};
+        self.downloaded(data);
         Ok(())
     }
+
+    fn downloaded(&self, data: &rss::TorrentData<'_>) {
+        self.handles.events.send(Event::Downloaded {
+            feed: self.feed.name.clone(),
+            matcher: data.original_matcher.name.clone(),
+            title: data.title.clone(),
+            save_folder: data.original_matcher.save_folder.clone(),
+        });
+    }
+
+    // download a .torrent file through this feed's client and settings
+    async fn fetch_torrent(&self, data: &rss::TorrentData<'_>) -> Result<Vec<u8>, Error> {
+        let _permit = self.handles.connections.acquire(&data.download_link).await;
+
+        let response = self
+            .feed
+            .http
+            .download(&self.client, &data.download_link)?
+            .send()
+            .await?;
+
+        let status = response.status();
+        let final_url = response.url().to_string();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string());
+        let body = response.bytes().await?;
+
+        rss::check_response(status, &final_url, content_type.as_deref(), &body)?;
+
+        Ok(body.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrents::ExemptReason;
+
+    use std::collections::HashMap;
+    use std::sync::Mutex as StdMutex;
+
+    use axum::extract::{Form, Query, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    // what the fake qbittorrent sends and what it was asked to do
+    #[derive(Debug, Default)]
+    struct Fake {
+        maindata: serde_json::Value,
+        trackers: HashMap<String, Vec<String>>,
+        calls: Vec<(String, String)>,
+        // torrents added, and whether adding them fails
+        adds: usize,
+        fail_adds: bool,
+    }
+
+    type Shared = Arc<StdMutex<Fake>>;
+
+    async fn record(state: &Shared, endpoint: &str, form: HashMap<String, String>) {
+        let hashes = form.get("hashes").cloned().unwrap_or_default();
+        state
+            .lock()
+            .unwrap()
+            .calls
+            .push((endpoint.to_string(), hashes));
+    }
+
+    // serve just the endpoints the monitor uses on a local port
+    async fn fake_qbittorrent(state: Shared) -> String {
+        let router =
+            Router::new()
+                .route("/api/v2/auth/login", post(|| async { "Ok." }))
+                .route(
+                    "/api/v2/sync/maindata",
+                    get(|State(state): State<Shared>| async move {
+                        Json(state.lock().unwrap().maindata.clone())
+                    }),
+                )
+                .route(
+                    "/api/v2/torrents/trackers",
+                    get(
+                        |State(state): State<Shared>,
+                         Query(query): Query<HashMap<String, String>>| async move {
+                            let trackers =
+                                state.lock().unwrap().trackers.get(&query["hash"]).cloned();
+                            let trackers = trackers.unwrap_or_default();
+                            Json(
+                                trackers
+                                    .into_iter()
+                                    .map(|url| serde_json::json!({ "url": url }))
+                                    .collect::<Vec<_>>(),
+                            )
+                        },
+                    ),
+                )
+                .route(
+                    "/api/v2/torrents/pause",
+                    post(|State(state): State<Shared>, Form(form)| async move {
+                        record(&state, "pause", form).await
+                    }),
+                )
+                .route(
+                    "/api/v2/torrents/add",
+                    post(|State(state): State<Shared>| async move {
+                        let mut fake = state.lock().unwrap();
+                        fake.adds += 1;
+                        if fake.fail_adds {
+                            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            axum::http::StatusCode::OK
+                        }
+                    }),
+                )
+                .route(
+                    "/api/v2/torrents/addTags",
+                    post(|State(state): State<Shared>, Form(form)| async move {
+                        record(&state, "addTags", form).await
+                    }),
+                )
+                .with_state(state);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        format! {"http://{}", address}
+    }
+
+    async fn monitor(state: &Shared) -> QbitMonitor {
+        let address = fake_qbittorrent(Arc::clone(state)).await;
+        let auth = serde_yaml::from_str(&format! {
+            "username: admin\npassword: adminadmin\naddress: {}\n\
+             trackers: [Keep.Example]\ntitle_bans: [CAM]",
+            address
+        })
+        .unwrap();
+
+        QbitMonitor::new(auth, EventSender::new(), Arc::new(Metrics::new()), None)
+            .await
+            .unwrap()
+    }
+
+    fn calls(state: &Shared) -> Vec<(String, String)> {
+        let mut calls = std::mem::take(&mut state.lock().unwrap().calls);
+        calls.sort();
+        calls
+    }
+
+    async fn pass(monitor: &mut QbitMonitor) {
+        monitor.sync_qbit().await.unwrap();
+        monitor.detect_resumes();
+        monitor.pause_all().await.unwrap();
+        monitor.check_titles().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exempt_torrents_are_left_alone() {
+        let state = Shared::default();
+        {
+            let mut fake = state.lock().unwrap();
+            fake.maindata = serde_json::json!({
+                "rid": 1,
+                "full_update": true,
+                "torrents": {
+                    "public": {"name": "Public", "progress": 1.0, "state": "uploading"},
+                    "category": {"name": "Category", "progress": 1.0, "state": "uploading",
+                                 "category": "AUTODL_EXEMPT"},
+                    "tagged": {"name": "Tagged", "progress": 1.0, "state": "uploading",
+                               "tags": "autodl, autodl:exempt"},
+                    "kept": {"name": "Kept", "progress": 1.0, "state": "uploading"},
+                    "running": {"name": "Running", "progress": 0.5, "state": "downloading"},
+                    "banned": {"name": "Show CAM", "progress": 0.5, "state": "downloading",
+                               "tags": "autodl"},
+                    "banned_exempt": {"name": "Movie CAM", "progress": 0.5,
+                                      "state": "downloading", "tags": "autodl,autodl:exempt"}
+                }
+            });
+            for hash in &["public", "category", "tagged"] {
+                let trackers = vec!["udp://open.example:1337/announce".to_string()];
+                fake.trackers.insert(hash.to_string(), trackers);
+            }
+            let trackers = vec!["https://keep.example/announce".to_string()];
+            fake.trackers.insert("kept".to_string(), trackers);
+        }
+
+        let mut monitor = monitor(&state).await;
+        pass(&mut monitor).await;
+
+        let expected = [
+            ("addTags", "banned"),
+            ("pause", "banned"),
+            ("pause", "public"),
+        ];
+        let expected = expected
+            .iter()
+            .map(|(x, y)| (x.to_string(), y.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(calls(&state), expected);
+        assert!(monitor.torrents.paused_for("public", PauseReason::Tracker));
+        assert!(monitor.torrents.paused_for("banned", PauseReason::Title));
+
+        // nothing is paused twice
+        pass(&mut monitor).await;
+        assert!(calls(&state).is_empty());
+    }
+
+    #[tokio::test]
+    async fn torrents_resumed_by_hand_are_exempted() {
+        let state = Shared::default();
+        {
+            let mut fake = state.lock().unwrap();
+            fake.maindata = serde_json::json!({
+                "rid": 1,
+                "full_update": true,
+                "torrents": {
+                    "public": {"name": "Public", "progress": 1.0, "state": "uploading"}
+                }
+            });
+            let trackers = vec!["udp://open.example:1337/announce".to_string()];
+            fake.trackers.insert("public".to_string(), trackers);
+        }
+
+        let mut monitor = monitor(&state).await;
+        pass(&mut monitor).await;
+        assert_eq!(calls(&state).len(), 1);
+
+        // qbittorrent reports it paused, then someone resumes it
+        state.lock().unwrap().maindata =
+            serde_json::json!({"rid": 2, "torrents": {"public": {"state": "pausedUP"}}});
+        pass(&mut monitor).await;
+        assert!(!monitor.torrents.is_exempt("public"));
+
+        state.lock().unwrap().maindata =
+            serde_json::json!({"rid": 3, "torrents": {"public": {"state": "stalledUP"}}});
+        pass(&mut monitor).await;
+
+        let exempt = monitor.torrents.list_exempt();
+        assert_eq!(exempt.len(), 1);
+        assert_eq!(exempt[0].reason, ExemptReason::ResumedByHand);
+        assert!(monitor.torrents.list_paused().is_empty());
+        assert!(calls(&state).is_empty());
+    }
+
+    // the `If-None-Match` of every request a fake feed got
+    type FeedRequests = Arc<StdMutex<Vec<Option<String>>>>;
+
+    const FEED: &str = "<?xml version=\"1.0\"?><rss><channel><item>\
+        <title>Show S01E01</title><link>magnet:?xt=urn:btih:0123</link>\
+        </item></channel></rss>";
+
+    // serve a feed with one item that answers with a 304 to its own etag
+    async fn fake_feed(requests: FeedRequests) -> String {
+        let router = Router::new()
+            .route(
+                "/rss",
+                get(
+                    |State(requests): State<FeedRequests>, headers: axum::http::HeaderMap| async move {
+                        let etag = headers
+                            .get("if-none-match")
+                            .and_then(|x| x.to_str().ok())
+                            .map(|x| x.to_string());
+                        requests.lock().unwrap().push(etag.clone());
+
+                        if etag.as_deref() == Some("\"v1\"") {
+                            (axum::http::StatusCode::NOT_MODIFIED, [("etag", "\"v1\"")], "")
+                        } else {
+                            (axum::http::StatusCode::OK, [("etag", "\"v1\"")], FEED)
+                        }
+                    },
+                ),
+            )
+            .with_state(requests);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        format! {"http://{}/rss", address}
+    }
+
+    // a feed task for `url` with one matcher that takes everything, saving under `dir`
+    fn feed_monitor(qbit: &QbitMonitor, url: &str, dir: &std::path::Path) -> FeedMonitor {
+        let mut feed: RssFeed = serde_yaml::from_str(&format! {
+            "name: tracker\nurl: {}\nupdate_interval: 60\nmatcher: []",
+            url
+        })
+        .unwrap();
+        let mut matcher: TorrentMatch = serde_yaml::from_str(&format! {
+            "name: shows\nsave_folder: {}",
+            dir.join("shows").display()
+        })
+        .unwrap();
+        matcher.lowercase();
+        feed.matcher = vec![matcher];
+
+        let handles = FeedHandles {
+            qbit_api: Arc::clone(&qbit.qbit_api),
+            limiter: Arc::new(RateLimiter::new(HashMap::new(), None)),
+            connections: Arc::new(HostConnections::new(None)),
+            events: EventSender::new(),
+            metrics: Arc::new(Metrics::new()),
+            status: Arc::new(Status::new()),
+            history: Arc::new(History::new()),
+            matcher_names: Arc::new(MatcherNames::default()),
+        };
+        FeedMonitor::from_feed(
+            feed,
+            reqwest::Client::new(),
+            false,
+            RetryConfig::default(),
+            handles,
+        )
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format! {"autodl-monitor-{}-{}", name, std::process::id()});
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn unchanged_feed_has_nothing_new() {
+        let state = Shared::default();
+        let requests = FeedRequests::default();
+        let url = fake_feed(Arc::clone(&requests)).await;
+        let dir = temp_dir("unchanged");
+        let qbit = monitor(&state).await;
+        let mut feed = feed_monitor(&qbit, &url, &dir);
+
+        feed.run_update().await.unwrap();
+        assert_eq!(feed.cache.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(state.lock().unwrap().adds, 1);
+
+        // the second poll is answered with a 304 and downloads nothing
+        feed.run_update().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![None, Some("\"v1\"".to_string())]
+        );
+        assert_eq!(state.lock().unwrap().adds, 1);
+        assert_eq!(feed.items.len(), 1);
+        assert_eq!(feed.handles.history.recent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_retried_on_the_next_poll() {
+        let state = Shared::default();
+        state.lock().unwrap().fail_adds = true;
+        let requests = FeedRequests::default();
+        let url = fake_feed(Arc::clone(&requests)).await;
+        let dir = temp_dir("failed");
+        let qbit = monitor(&state).await;
+        let mut feed = feed_monitor(&qbit, &url, &dir);
+
+        // the validators are not kept, so the feed is fetched in full again
+        feed.run_update().await.unwrap();
+        assert!(feed.cache.etag.is_none());
+        state.lock().unwrap().fail_adds = false;
+        feed.run_update().await.unwrap();
+        assert_eq!(feed.cache.etag.as_deref(), Some("\"v1\""));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(*requests.lock().unwrap(), vec![None, None]);
+        assert_eq!(state.lock().unwrap().adds, 2);
+        let outcomes = feed
+            .handles
+            .history
+            .recent()
+            .into_iter()
+            .map(|x| x.outcome == Outcome::Downloaded)
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, vec![true, false]);
+    }
+
+    // send `command` to the feed task and wait for its answer
+    async fn command<T>(
+        feed: &mut FeedMonitor,
+        command: impl FnOnce(tokio::sync::oneshot::Sender<Result<T, Error>>) -> FeedCommand,
+    ) -> Result<T, Error> {
+        let (reply, response) = tokio::sync::oneshot::channel();
+        feed.handle_command(command(reply)).await;
+        response.await.unwrap()
+    }
+
+    fn matcher(yaml: &str) -> TorrentMatch {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_matchers_keep_names_unique() {
+        let state = Shared::default();
+        let qbit = monitor(&state).await;
+        let dir = std::env::temp_dir();
+        let mut feed = feed_monitor(&qbit, "http://127.0.0.1:1/rss", &dir);
+
+        // `hd` is a top level matcher, `movies` belongs to another feed
+        let named = vec!["hd".to_string()].into_iter().collect();
+        let taken = vec!["shows".to_string(), "movies".to_string()]
+            .into_iter()
+            .collect();
+        feed.handles.matcher_names = Arc::new(MatcherNames::new(named, taken));
+
+        for name in ["movies", "hd", "shows"].iter() {
+            let added = matcher(&format! {"name: {}\nsave_folder: /x", name});
+            match command(&mut feed, |reply| FeedCommand::AddMatcher(added, reply)).await {
+                Err(Error::DuplicateName { kind, name: taken }) => {
+                    assert_eq!((kind, taken.as_str()), ("matcher", *name))
+                }
+                x => panic!("unexpected {:?}", x),
+            }
+        }
+        assert_eq!(feed.feed.matcher.len(), 1);
+
+        // generated names stay clear of every other matcher
+        let added = matcher("save_folder: /downloads/movies");
+        let index = command(&mut feed, |reply| FeedCommand::AddMatcher(added, reply))
+            .await
+            .unwrap();
+        assert_eq!(feed.feed.matcher[index].name, "movies-2");
+
+        // a replaced matcher can keep its own name, but not take another one
+        let updated = matcher("name: shows\nsave_folder: /y");
+        command(&mut feed, |reply| {
+            FeedCommand::UpdateMatcher(0, updated, reply)
+        })
+        .await
+        .unwrap();
+        let updated = matcher("name: movies-2\nsave_folder: /y");
+        assert!(command(&mut feed, |reply| FeedCommand::UpdateMatcher(
+            0, updated, reply
+        ))
+        .await
+        .is_err());
+        assert_eq!(feed.feed.matcher[0].name, "shows");
+
+        // removing a matcher frees its name
+        command(&mut feed, |reply| FeedCommand::RemoveMatcher(0, reply))
+            .await
+            .unwrap();
+        let added = matcher("name: shows\nsave_folder: /x");
+        command(&mut feed, |reply| FeedCommand::AddMatcher(added, reply))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn paused_feeds_stay_alive() {
+        let state = Shared::default();
+        let qbit = monitor(&state).await;
+        let dir = std::env::temp_dir();
+        let mut feed = feed_monitor(&qbit, "http://127.0.0.1:1/rss", &dir);
+        let status = Arc::clone(&feed.handles.status);
+        let config = crate::status::HealthConfig {
+            feed_grace: 0,
+            ..Default::default()
+        };
+        status.qbit_task(true);
+        status.feed_task("tracker", true);
+
+        // the last poll was due a while ago, so the feed looks stuck
+        status.update_feed("tracker", &feed.health, std::time::Duration::from_secs(0));
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(!status.liveness(&config).healthy);
+
+        let (control, mut commands) = crate::control::channel(0, "tracker", "");
+        control.pause();
+        let shutdown = Shutdown::new();
+        let task = {
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move { feed.run(&mut commands, &shutdown).await })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        shutdown.trigger();
+        task.await.unwrap();
+
+        let report = status.liveness(&config);
+        assert!(report.healthy);
+        assert!(report.feeds[0].paused);
+    }
 }
@@ -0,0 +1,387 @@
+//!
+//! webhook notifications for events
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::email::{self, EmailConfig};
+use super::events::{Event, EventKind, EventSender};
+use super::secret::Secret;
+use super::supervisor::Shutdown;
+use super::Error;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Deserialize, JsonSchema, Default, Clone)]
+pub struct NotificationConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    pub email: Option<EmailConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct WebhookConfig {
+    pub url: Secret,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// body to send instead of the one `format` would produce. `{event}`, `{message}`
+    /// and `{subject}` are replaced with the json escaped values from the event
+    pub template: Option<String>,
+    /// only send these events, all of them by default
+    pub events: Option<Vec<EventKind>>,
+    #[serde(default)]
+    pub headers: HashMap<String, Secret>,
+    /// attempts after the first one fails
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// the event itself as json, with an extra `message` field
+    #[default]
+    Json,
+    Discord,
+    Slack,
+    Gotify,
+    /// plain text body with a `Title` header
+    Ntfy,
+}
+
+/// start every configured notifier. they send what is still queued once `shutdown`
+/// is triggered and then stop
+pub fn spawn(
+    config: &NotificationConfig,
+    events: &EventSender,
+    shutdown: &Shutdown,
+) -> Vec<JoinHandle<()>> {
+    let mut tasks = spawn_webhooks(config, events, shutdown);
+
+    if let Some(email) = &config.email {
+        tasks.push(email::spawn_digest(email, events, shutdown));
+    }
+
+    tasks
+}
+
+/// start a task for every configured webhook that forwards events as they happen
+pub fn spawn_webhooks(
+    config: &NotificationConfig,
+    events: &EventSender,
+    shutdown: &Shutdown,
+) -> Vec<JoinHandle<()>> {
+    let client = reqwest::Client::new();
+    let mut tasks = Vec::new();
+
+    for webhook in &config.webhooks {
+        let webhook = webhook.clone();
+        let mut receiver = events.subscribe();
+        let client = client.clone();
+        let shutdown = shutdown.clone();
+
+        tasks.push(tokio::spawn(async move {
+            loop {
+                // queued events go first, so stopping only happens once they are sent
+                let event = tokio::select! {
+                    biased;
+                    event = receiver.recv() => event,
+                    _ = shutdown.wait() => break,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        println! {"webhook fell behind and skipped {} events", missed};
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if !webhook.wants(&event) {
+                    continue;
+                }
+
+                if let Err(e) = webhook.deliver(&client, &event).await {
+                    println! {"could not deliver webhook for {:?}: {}", event.kind(), e};
+                }
+            }
+        }));
+    }
+
+    tasks
+}
+
+impl WebhookConfig {
+    fn wants(&self, event: &Event) -> bool {
+        match &self.events {
+            Some(kinds) => kinds.contains(&event.kind()),
+            None => true,
+        }
+    }
+
+    /// send an event, retrying with exponential backoff
+    pub async fn deliver(&self, client: &reqwest::Client, event: &Event) -> Result<(), Error> {
+        let mut attempt = 0;
+
+        loop {
+            match self.send(client, event).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retries && e.is_retryable() => {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_secs(1 << attempt.min(6))).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send(&self, client: &reqwest::Client, event: &Event) -> Result<(), Error> {
+        let mut request = client.post(self.url.resolve()?);
+
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.resolve()?);
+        }
+
+        let request = match (&self.template, self.format) {
+            (Some(template), WebhookFormat::Ntfy) => request.body(fill_template(template, event)),
+            (Some(template), _) => request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(fill_template(template, event)),
+            (None, WebhookFormat::Ntfy) => request
+                .header("Title", format! {"autodl-rss: {}", kind_name(event)})
+                .body(event.message()),
+            (None, format) => request.json(&payload(format, event)?),
+        };
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+fn payload(format: WebhookFormat, event: &Event) -> Result<serde_json::Value, Error> {
+    let message = event.message();
+
+    let value = match format {
+        WebhookFormat::Json | WebhookFormat::Ntfy => {
+            let mut value = serde_json::to_value(event)?;
+            value["message"] = serde_json::Value::String(message);
+            value
+        }
+        WebhookFormat::Discord => serde_json::json!({ "content": message }),
+        WebhookFormat::Slack => serde_json::json!({ "text": message }),
+        WebhookFormat::Gotify => serde_json::json!({
+            "title": format!{"autodl-rss: {}", kind_name(event)},
+            "message": message,
+        }),
+    };
+
+    Ok(value)
+}
+
+fn kind_name(event: &Event) -> String {
+    serde_json::to_value(event.kind())
+        .ok()
+        .and_then(|x| x.as_str().map(|x| x.to_string()))
+        .unwrap_or_default()
+}
+
+fn fill_template(template: &str, event: &Event) -> String {
+    // escape for use inside a json string, without the surrounding quotes
+    let escape = |value: &str| {
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        quoted
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    template
+        .replace("{event}", &escape(&kind_name(event)))
+        .replace("{message}", &escape(&event.message()))
+        .replace("{subject}", &escape(event.subject()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    // a local http server that answers with `statuses` in turn, then 200, and passes
+    // every request on as its head and body
+    async fn server(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format! {"http://{}/hook", listener.local_addr().unwrap()};
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+
+                let end = loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    if let Some(end) = request.windows(4).position(|x| x == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|x| x.strip_prefix("content-length: "))
+                    .map_or(0, |x| x.trim().parse().unwrap());
+                while request.len() < end + length {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let body = String::from_utf8_lossy(&request[end..]).to_string();
+
+                let status = statuses.next().unwrap_or(200);
+                let response = format! {
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                sender.send((head, body)).unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn webhook(url: &str, extra: &str) -> WebhookConfig {
+        serde_yaml::from_str(&format! {"url: {}\n{}", url, extra}).unwrap()
+    }
+
+    fn downloaded() -> Event {
+        Event::Downloaded {
+            feed: "tracker".to_string(),
+            matcher: "shows".to_string(),
+            title: "Some \"Show\" S01E01".to_string(),
+            save_folder: "/downloads".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn formats_are_sent_as_json() {
+        let (url, mut requests) = server(vec![]).await;
+        let client = reqwest::Client::new();
+
+        let hook = webhook(&url, "format: discord\nheaders: {X-Token: abc123}");
+        hook.deliver(&client, &downloaded()).await.unwrap();
+        let (head, body) = requests.recv().await.unwrap();
+        assert!(head.starts_with("post /hook http/1.1"));
+        assert!(head.contains("x-token: abc123"));
+        assert!(head.contains("content-type: application/json"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["content"],
+            "downloading Some \"Show\" S01E01 from tracker"
+        );
+
+        let hook = webhook(&url, "format: json");
+        hook.deliver(&client, &downloaded()).await.unwrap();
+        let (_, body) = requests.recv().await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "downloaded");
+        assert_eq!(body["save_folder"], "/downloads");
+        assert!(body["message"].as_str().unwrap().starts_with("downloading"));
+
+        let hook = webhook(&url, "format: ntfy");
+        hook.deliver(&client, &downloaded()).await.unwrap();
+        let (head, body) = requests.recv().await.unwrap();
+        assert!(head.contains("title: autodl-rss: downloaded"));
+        assert_eq!(body, "downloading Some \"Show\" S01E01 from tracker");
+    }
+
+    #[tokio::test]
+    async fn template_values_are_escaped() {
+        let (url, mut requests) = server(vec![]).await;
+        let hook = webhook(
+            &url,
+            r#"template: '{"kind": "{event}", "name": "{subject}", "text": "{message}"}'"#,
+        );
+
+        hook.deliver(&reqwest::Client::new(), &downloaded())
+            .await
+            .unwrap();
+        let (head, body) = requests.recv().await.unwrap();
+        assert!(head.contains("content-type: application/json"));
+
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["kind"], "downloaded");
+        assert_eq!(body["name"], "Some \"Show\" S01E01");
+        assert_eq!(
+            body["text"],
+            "downloading Some \"Show\" S01E01 from tracker"
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (url, mut requests) = server(vec![503, 200, 404]).await;
+        let client = reqwest::Client::new();
+
+        let hook = webhook(&url, "retries: 1");
+        hook.deliver(&client, &downloaded()).await.unwrap();
+        assert!(requests.recv().await.is_some());
+        assert!(requests.recv().await.is_some());
+
+        // client errors are not worth a second attempt
+        assert!(hook.deliver(&client, &downloaded()).await.is_err());
+        assert!(requests.recv().await.is_some());
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn only_wanted_events_are_sent() {
+        let (url, mut requests) = server(vec![]).await;
+        let config = NotificationConfig {
+            webhooks: vec![webhook(&url, "events: [feed_failing]")],
+            email: None,
+        };
+        let events = EventSender::new();
+        let shutdown = Shutdown::new();
+        let tasks = spawn_webhooks(&config, &events, &shutdown);
+
+        events.send(downloaded());
+        events.send(Event::FeedFailing {
+            feed: "tracker".to_string(),
+            failures: 3,
+            error: "timed out".to_string(),
+        });
+
+        // the downloaded event went first, so it would have arrived first
+        let (_, body) = requests.recv().await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "feed_failing");
+        assert_eq!(body["failures"], 3);
+
+        // what is queued at shutdown is still sent
+        events.send(Event::FeedFailing {
+            feed: "tracker".to_string(),
+            failures: 4,
+            error: "timed out".to_string(),
+        });
+        shutdown.trigger();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let (_, body) = requests.recv().await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["failures"], 4);
+    }
+}
@@ -0,0 +1,312 @@
+//!
+//! client for the qbittorrent web api, used for everything the daemon asks of
+//! qbittorrent
+//!
+
+use super::mirror::MainData;
+use super::redact;
+use super::secret::Secret;
+use super::yaml::QbittorrentAuthentication;
+use super::{Context, Error};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::Mutex;
+
+#[derive(Debug)]
+pub struct QbitApi {
+    // never proxied: the web ui is expected to be reachable directly
+    client: reqwest::Client,
+    address: String,
+    username: String,
+    password: Secret,
+    // held while logging in, so that requests finding the session expired at the
+    // same time log in one after another
+    login_lock: Mutex<()>,
+    // logins so far. a request that was sent before the last login skips its own
+    logins: AtomicU64,
+}
+
+/// options sent along with a torrent added through [`QbitApi::add_torrent_file`] or
+/// [`QbitApi::add_torrent_urls`]
+#[derive(Debug, Default)]
+pub struct AddTorrent {
+    pub savepath: String,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub paused: bool,
+}
+
+impl AddTorrent {
+    fn form(&self, mut form: reqwest::multipart::Form) -> reqwest::multipart::Form {
+        form = form
+            .text("savepath", self.savepath.clone())
+            .text("paused", self.paused.to_string());
+
+        if let Some(category) = &self.category {
+            form = form.text("category", category.clone());
+        }
+        if !self.tags.is_empty() {
+            form = form.text("tags", self.tags.join(","));
+        }
+
+        form
+    }
+}
+
+impl QbitApi {
+    pub async fn new(auth: &QbittorrentAuthentication) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .no_proxy()
+            .build()?;
+
+        let qbit_api = Self {
+            client,
+            address: auth.address.trim_end_matches('/').to_string(),
+            username: auth.username.clone(),
+            password: auth.password.clone(),
+            login_lock: Mutex::new(()),
+            logins: AtomicU64::new(0),
+        };
+
+        qbit_api.login().await?;
+        Ok(qbit_api)
+    }
+
+    // log in again, unless another request already did since `seen` logins, which is
+    // how many there were when the rejected request was sent
+    async fn login_after(&self, seen: u64) -> Result<(), Error> {
+        let _guard = self.login_lock.lock().await;
+        if self.logins.load(Ordering::Acquire) != seen {
+            return Ok(());
+        }
+
+        self.login().await
+    }
+
+    async fn login(&self) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(self.url("auth/login"))
+            .form(&[
+                ("username", &self.username),
+                ("password", &self.password.resolve()?),
+            ])
+            .send()
+            .await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+
+        // qbittorrent answers a bad login with a 200 and "Fails."
+        if !status.is_success() || body.trim() != "Ok." {
+            return Err(Error::AccessDenied {
+                status: status.as_u16(),
+                url: self.url("auth/login"),
+                snippet: redact::text(&body),
+            });
+        }
+
+        self.logins.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format! {"{}/api/v2/{}", self.address, endpoint}
+    }
+
+    /// send a request built by `build`, logging in again once if the session expired
+    pub(crate) async fn send<F>(&self, build: F) -> Result<reqwest::Response, Error>
+    where
+        F: Fn(&reqwest::Client, &dyn Fn(&str) -> String) -> reqwest::RequestBuilder,
+    {
+        let url = |endpoint: &str| self.url(endpoint);
+
+        let seen = self.logins.load(Ordering::Acquire);
+        let mut response = build(&self.client, &url).send().await?;
+
+        if response.status() == reqwest::StatusCode::FORBIDDEN {
+            self.login_after(seen).await?;
+            response = build(&self.client, &url).send().await?;
+        }
+
+        Ok(response.error_for_status()?)
+    }
+
+    /// upload the contents of a .torrent file that we downloaded ourselves
+    pub async fn add_torrent_file(
+        &self,
+        name: &str,
+        torrent: Vec<u8>,
+        options: &AddTorrent,
+    ) -> Result<(), Error> {
+        self.send(|client, url| {
+            let file = reqwest::multipart::Part::bytes(torrent.clone())
+                .file_name(format! {"{}.torrent", name})
+                .mime_str("application/x-bittorrent")
+                .expect("static mime type is valid");
+
+            let form = options.form(reqwest::multipart::Form::new().part("torrents", file));
+            client.post(url("torrents/add")).multipart(form)
+        })
+        .await
+        .item_context(name)?;
+
+        Ok(())
+    }
+
+    /// have qbittorrent download a torrent or magnet link itself
+    pub async fn add_torrent_urls(&self, urls: &str, options: &AddTorrent) -> Result<(), Error> {
+        self.send(|client, url| {
+            let form = options.form(reqwest::multipart::Form::new().text("urls", urls.to_string()));
+            client.post(url("torrents/add")).multipart(form)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn add_tags(&self, hash: &str, tags: &str) -> Result<(), Error> {
+        self.send(|client, url| {
+            client
+                .post(url("torrents/addTags"))
+                .form(&[("hashes", hash), ("tags", tags)])
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_tags(&self, hash: &str, tags: &str) -> Result<(), Error> {
+        self.send(|client, url| {
+            client
+                .post(url("torrents/removeTags"))
+                .form(&[("hashes", hash), ("tags", tags)])
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn resume(&self, hash: &str) -> Result<(), Error> {
+        self.send(|client, url| {
+            client
+                .post(url("torrents/resume"))
+                .form(&[("hashes", hash)])
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// changes to the torrent list since response `rid`, or everything for a `rid` of 0
+    pub async fn maindata(&self, rid: u64) -> Result<MainData, Error> {
+        let rid = rid.to_string();
+        let response = self
+            .send(|client, url| client.get(url("sync/maindata")).query(&[("rid", &rid)]))
+            .await?;
+
+        Ok(response.json().await?)
+    }
+
+    /// urls of every tracker of a torrent
+    pub async fn trackers(&self, hash: &str) -> Result<Vec<String>, Error> {
+        #[derive(serde::Deserialize)]
+        struct Tracker {
+            url: String,
+        }
+
+        let response = self
+            .send(|client, url| {
+                client
+                    .get(url("torrents/trackers"))
+                    .query(&[("hash", hash)])
+            })
+            .await?;
+        let trackers: Vec<Tracker> = response.json().await?;
+
+        Ok(trackers.into_iter().map(|x| x.url).collect())
+    }
+
+    pub async fn pause(&self, hash: &str) -> Result<(), Error> {
+        self.send(|client, url| client.post(url("torrents/pause")).form(&[("hashes", hash)]))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+
+    // logins made, and whether the session is still valid
+    #[derive(Debug, Default)]
+    struct Fake {
+        logins: AtomicU64,
+        session: AtomicBool,
+    }
+
+    async fn fake_qbittorrent(fake: Arc<Fake>) -> String {
+        let router = Router::new()
+            .route(
+                "/api/v2/auth/login",
+                post(|State(fake): State<Arc<Fake>>| async move {
+                    fake.logins.fetch_add(1, Ordering::SeqCst);
+                    fake.session.store(true, Ordering::SeqCst);
+                    "Ok."
+                }),
+            )
+            .route(
+                "/api/v2/torrents/resume",
+                post(|State(fake): State<Arc<Fake>>| async move {
+                    if fake.session.load(Ordering::SeqCst) {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::FORBIDDEN
+                    }
+                }),
+            )
+            .with_state(fake);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        format! {"http://{}", address}
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn expired_sessions_are_renewed_once() {
+        let fake = Arc::new(Fake::default());
+        let address = fake_qbittorrent(Arc::clone(&fake)).await;
+        let auth = serde_yaml::from_str(&format! {
+            "username: admin\npassword: adminadmin\naddress: {}\ntrackers: []",
+            address
+        })
+        .unwrap();
+        let api = Arc::new(QbitApi::new(&auth).await.unwrap());
+
+        // every request finds the session expired at the same time
+        fake.session.store(false, Ordering::SeqCst);
+        let requests = (0..8)
+            .map(|_| {
+                let api = Arc::clone(&api);
+                tokio::spawn(async move { api.resume("0123").await })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            request.await.unwrap().unwrap();
+        }
+
+        assert_eq!(fake.logins.load(Ordering::SeqCst), 2);
+    }
+}
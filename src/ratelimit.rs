@@ -0,0 +1,198 @@
+//!
+//! token bucket rate limiting per tracker host, shared by every feed
+//!
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+// follows the tokio clock, so the tests can pause it
+use tokio::time::Instant;
+
+/// allow `requests` requests every `per` seconds, bursting up to `requests` at once
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // may go negative: every caller reserves a token up front and waits for it to refill
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    // keyed by host or domain, `tracker.example` also covers `rss.tracker.example`
+    limits: HashMap<String, RateLimit>,
+    // applied to any host without its own entry
+    default: Option<RateLimit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, RateLimit>, default: Option<RateLimit>) -> Self {
+        let limits = limits
+            .into_iter()
+            .map(|(host, limit)| (host.to_ascii_lowercase(), limit))
+            .collect();
+
+        Self {
+            limits,
+            default,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// wait until a request to `url` is allowed
+    pub async fn acquire(&self, url: &str) {
+        let host = match reqwest::Url::parse(url)
+            .ok()
+            .and_then(|x| x.host_str().map(|x| x.to_ascii_lowercase()))
+        {
+            Some(host) => host,
+            None => return,
+        };
+
+        let (key, limit) = match self.limit_for(&host) {
+            Some(x) => x,
+            None => return,
+        };
+
+        if limit.requests == 0 || limit.per == 0 {
+            return;
+        }
+
+        let capacity = limit.requests as f64;
+        let rate = capacity / limit.per as f64;
+
+        let wait = {
+            let mut buckets = self.buckets.lock().await;
+            let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            });
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+            bucket.last_refill = now;
+
+            bucket.tokens -= 1.0;
+
+            if bucket.tokens >= 0.0 {
+                None
+            } else {
+                Some(Duration::from_secs_f64(-bucket.tokens / rate))
+            }
+        };
+
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn limit_for(&self, host: &str) -> Option<(String, &RateLimit)> {
+        // prefer the most specific matching domain
+        let matched = self
+            .limits
+            .iter()
+            .filter(|(domain, _)| {
+                host == domain.as_str() || host.ends_with(&format! {".{}", domain})
+            })
+            .max_by_key(|(domain, _)| domain.len());
+
+        match matched {
+            Some((domain, limit)) => Some((domain.clone(), limit)),
+            None => self.default.as_ref().map(|limit| (host.to_string(), limit)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: &[(&str, u32, u64)], default: Option<(u32, u64)>) -> RateLimiter {
+        let limits = limits
+            .iter()
+            .map(|(host, requests, per)| {
+                let limit = RateLimit {
+                    requests: *requests,
+                    per: *per,
+                };
+                (host.to_string(), limit)
+            })
+            .collect();
+        let default = default.map(|(requests, per)| RateLimit { requests, per });
+        RateLimiter::new(limits, default)
+    }
+
+    #[test]
+    fn most_specific_domain_wins() {
+        let limiter = limiter(
+            &[("Tracker.example", 1, 1), ("rss.tracker.example", 2, 1)],
+            None,
+        );
+
+        let (key, limit) = limiter.limit_for("rss.tracker.example").unwrap();
+        assert_eq!((key.as_str(), limit.requests), ("rss.tracker.example", 2));
+        let (key, limit) = limiter.limit_for("www.tracker.example").unwrap();
+        assert_eq!((key.as_str(), limit.requests), ("tracker.example", 1));
+
+        // only whole labels match
+        assert!(limiter.limit_for("eviltracker.example").is_none());
+    }
+
+    #[test]
+    fn default_is_kept_per_host() {
+        let limiter = limiter(&[], Some((3, 1)));
+
+        let (key, limit) = limiter.limit_for("a.example").unwrap();
+        assert_eq!((key.as_str(), limit.requests), ("a.example", 3));
+        let (key, _) = limiter.limit_for("b.example").unwrap();
+        assert_eq!(key, "b.example");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_then_waits_for_refill() {
+        let limiter = limiter(&[("tracker.example", 2, 10)], None);
+        let start = Instant::now();
+
+        limiter.acquire("https://tracker.example/rss").await;
+        limiter.acquire("https://rss.tracker.example/rss").await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // a token comes back every 5 seconds
+        limiter.acquire("https://tracker.example/rss").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        limiter.acquire("https://tracker.example/rss").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        // an idle bucket refills, but never past its capacity
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let idle = Instant::now();
+        limiter.acquire("https://tracker.example/rss").await;
+        limiter.acquire("https://tracker.example/rss").await;
+        assert_eq!(idle.elapsed(), Duration::ZERO);
+        limiter.acquire("https://tracker.example/rss").await;
+        assert_eq!(idle.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_hosts_never_wait() {
+        let limiter = limiter(&[("tracker.example", 1, 60), ("off.example", 0, 60)], None);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            limiter.acquire("https://other.example/rss").await;
+            limiter.acquire("https://off.example/rss").await;
+            limiter.acquire("not a url").await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -1,13 +1,14 @@
-///
-/// custom RSS parsing for non-standard rss feeds
-///
+//!
+//! custom RSS parsing for non-standard rss feeds
+//!
 
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 
+use super::redact;
 use super::yaml;
-use super::Error;
+use super::{Context, Error};
 
 use serde::{Deserialize, Serialize};
 use serde_yaml;
@@ -62,8 +63,11 @@ impl Item {
             return Ok(link.clone());
         }
 
-        dbg! {"link missing SerdeMissing"};
-        Err(Error::SerdeMissing)
+        let err = Error::SerdeMissing("link");
+        match &self.title {
+            Some(title) => Err(err.item(title.as_str())),
+            None => Err(err),
+        }
     }
 }
 
@@ -77,7 +81,38 @@ impl Torrent {
     }
 }
 
-#[derive(Debug, Serialize)]
+/// validators from the last successful fetch of a feed, sent back on the next
+/// request so that the server can answer with a 304 when nothing has changed
+#[derive(Debug, Default, Clone)]
+pub struct FeedCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+impl FeedCache {
+    pub fn apply(&self, headers: &mut reqwest::header::HeaderMap) -> Result<(), Error> {
+        if let Some(etag) = &self.etag {
+            headers.insert(reqwest::header::IF_NONE_MATCH, etag.parse()?);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.insert(reqwest::header::IF_MODIFIED_SINCE, last_modified.parse()?);
+        }
+        Ok(())
+    }
+
+    pub fn update(&mut self, headers: &reqwest::header::HeaderMap) {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+                .map(|x| x.to_string())
+        };
+
+        self.etag = get(reqwest::header::ETAG);
+        self.last_modified = get(reqwest::header::LAST_MODIFIED);
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
 pub struct SerdeTorrentData {
     pub title: String,
     pub tags: HashSet<String>,
@@ -95,10 +130,7 @@ impl SerdeTorrentData {
 
         let _title = match &item.title {
             Some(title) => title.to_lowercase(),
-            None => {
-                dbg! {"missing title sending SerdeMissing"};
-                return Err(Error::SerdeMissing);
-            }
+            None => return Err(Error::SerdeMissing("title")),
         };
         let tags = match &item.tags {
             Some(tags) => tags
@@ -122,6 +154,19 @@ impl SerdeTorrentData {
     }
 }
 
+/// result of fetching a feed
+#[derive(Debug, Default)]
+pub struct Fetched<'a> {
+    // number of items in the feed
+    pub parsed: usize,
+    // items accepted by one of the feed's matchers
+    pub matched: Vec<TorrentData<'a>>,
+    // every item in the feed, or `None` when it has not changed since the last fetch
+    pub items: Option<Vec<SerdeTorrentData>>,
+    // validators of this fetch, to be kept once every matched item was downloaded
+    pub cache: Option<FeedCache>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TorrentData<'a> {
     pub title: String,
@@ -129,9 +174,17 @@ pub struct TorrentData<'a> {
     pub download_link: String,
     pub size: Option<u64>,
     pub item_hash: u64,
-    #[serde(skip)]
+    // only the name of the matcher goes into the metadata file
+    #[serde(rename = "matcher", serialize_with = "matcher_name")]
     pub original_matcher: &'a yaml::TorrentMatch,
 }
+
+fn matcher_name<S: serde::Serializer>(
+    matcher: &&yaml::TorrentMatch,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&matcher.name)
+}
 impl<'a> TorrentData<'a> {
     pub fn from_serde_data(data: SerdeTorrentData, matcher: &'a yaml::TorrentMatch) -> Self {
         TorrentData {
@@ -143,23 +196,111 @@ impl<'a> TorrentData<'a> {
             original_matcher: matcher,
         }
     }
+    /// copy the item out, without the borrow of its matcher
+    pub fn detach(&self) -> SerdeTorrentData {
+        SerdeTorrentData {
+            title: self.title.clone(),
+            tags: self.tags.clone(),
+            download_link: self.download_link.clone(),
+            size: self.size,
+            item_hash: self.item_hash,
+        }
+    }
+
     pub fn write_metadata(&self) -> Result<(), Error> {
         let title =
             format! {"{}\\__META_{}.yaml", self.original_matcher.save_folder, self.item_hash};
-        let buffer = match std::fs::File::create(&title) {
-            Ok(buffer) => buffer,
-            Err(e) => {
-                println! {"ERROR WHEN WRITING METADATA OF {}:\n\t{}\n\t{}\n\tORIGINAL PATH:{}", self.title, self.original_matcher.save_folder, e, title}
-                return Err(Error::from(e));
-            }
-        };
+        let buffer = std::fs::File::create(&title).item_context(self.title.as_str())?;
 
-        let _ser = serde_yaml::to_writer(buffer, &self);
+        serde_yaml::to_writer(buffer, &self).item_context(self.title.as_str())?;
 
         Ok(())
     }
 }
 
+/// make sure a feed response is actually a feed before handing it to the xml parser,
+/// so that login pages and tracker outages are reported as such
+pub fn check_response(
+    status: reqwest::StatusCode,
+    final_url: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<(), Error> {
+    let url = redact::url(final_url);
+
+    if !status.is_success() {
+        let status_code = status.as_u16();
+        let snippet = snippet(body);
+
+        return Err(match status_code {
+            401 | 403 => Error::AccessDenied {
+                status: status_code,
+                url,
+                snippet,
+            },
+            // feeds check for this before getting here, .torrent downloads do not
+            429 => Error::RateLimited {
+                status: status_code,
+                retry_after: None,
+            },
+            500..=599 => Error::ServerError {
+                status: status_code,
+                url,
+                snippet,
+            },
+            _ => Error::UnexpectedStatus {
+                status: status_code,
+                url,
+                snippet,
+            },
+        });
+    }
+
+    if body.iter().all(|x| x.is_ascii_whitespace()) {
+        return Err(Error::MissingBytes { url });
+    }
+
+    if looks_like_html(content_type, body) {
+        return Err(Error::HtmlResponse {
+            status: status.as_u16(),
+            url,
+            snippet: snippet(body),
+        });
+    }
+
+    Ok(())
+}
+
+fn looks_like_html(content_type: Option<&str>, body: &[u8]) -> bool {
+    let start = body
+        .iter()
+        .skip_while(|x| x.is_ascii_whitespace())
+        .take(64)
+        .map(|x| x.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if start.starts_with(b"<!doctype html") || start.starts_with(b"<html") {
+        return true;
+    }
+
+    // some trackers serve their rss as text/html, so only trust the header
+    // when the body does not look like xml either
+    let html_header = content_type
+        .map(|x| x.to_ascii_lowercase().contains("text/html"))
+        .unwrap_or(false);
+
+    html_header && !(start.starts_with(b"<?xml") || start.starts_with(b"<rss"))
+}
+
+// the first part of a response body, squashed onto a single line for error messages
+fn snippet(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(&body[..body.len().min(512)]);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    // login pages like to echo the request back, passkey and all
+    redact::text(&text.chars().take(200).collect::<String>())
+}
+
 pub fn xml_to_torrents<T: std::io::BufRead>(data: T) -> Result<Vec<SerdeTorrentData>, Error> {
     let doc: Document = quick_xml::de::from_reader(data)?;
 
@@ -168,16 +309,168 @@ pub fn xml_to_torrents<T: std::io::BufRead>(data: T) -> Result<Vec<SerdeTorrentD
             let t_data = items
                 .into_iter()
                 .map(SerdeTorrentData::new)
-                .filter_map(|item| item.ok())
+                .filter_map(|item| match item {
+                    Ok(item) => Some(item),
+                    Err(e) => {
+                        println! {"skipping rss item: {}", e};
+                        None
+                    }
+                })
                 .collect::<Vec<_>>();
 
             Ok(t_data)
         } else {
-            dbg! {"missing channel.item sending serdemissing"};
-            Err(Error::SerdeMissing)
+            Err(Error::SerdeMissing("channel.item"))
         }
     } else {
-        dbg! {"missing channel sending sedemissing"};
-        Err(Error::SerdeMissing)
+        Err(Error::SerdeMissing("channel"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    const RSS: &[u8] = b"<?xml version=\"1.0\"?><rss><channel></channel></rss>";
+    const LOGIN: &[u8] = b"\n  <!DOCTYPE html>\n<html><head><title>Login</title></head>\n<body>please log in</body></html>";
+
+    fn check(status: u16, content_type: Option<&str>, body: &[u8]) -> Result<(), Error> {
+        check_response(
+            StatusCode::from_u16(status).unwrap(),
+            "https://tracker.example/rss?passkey=0123456789",
+            content_type,
+            body,
+        )
+    }
+
+    #[test]
+    fn cache_sends_back_the_validators() {
+        let mut response = reqwest::header::HeaderMap::new();
+        response.insert(reqwest::header::ETAG, "\"abc\"".parse().unwrap());
+        response.insert(
+            reqwest::header::LAST_MODIFIED,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+
+        let mut cache = FeedCache::default();
+        cache.update(&response);
+
+        let mut request = reqwest::header::HeaderMap::new();
+        cache.apply(&mut request).unwrap();
+        assert_eq!(request[reqwest::header::IF_NONE_MATCH], "\"abc\"");
+        assert_eq!(
+            request[reqwest::header::IF_MODIFIED_SINCE],
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        );
+    }
+
+    #[test]
+    fn cache_forgets_validators_the_server_stopped_sending() {
+        let mut response = reqwest::header::HeaderMap::new();
+        response.insert(reqwest::header::ETAG, "\"abc\"".parse().unwrap());
+        let mut cache = FeedCache::default();
+        cache.update(&response);
+
+        cache.update(&reqwest::header::HeaderMap::new());
+        let mut request = reqwest::header::HeaderMap::new();
+        cache.apply(&mut request).unwrap();
+        assert!(request.is_empty());
+    }
+
+    #[test]
+    fn accepts_rss() {
+        assert!(check(200, Some("application/rss+xml"), RSS).is_ok());
+        // some trackers label their feeds as html
+        assert!(check(200, Some("text/html; charset=utf-8"), RSS).is_ok());
+    }
+
+    #[test]
+    fn login_page_is_html() {
+        match check(200, Some("text/html"), LOGIN) {
+            Err(Error::HtmlResponse {
+                status,
+                url,
+                snippet,
+            }) => {
+                assert_eq!(status, 200);
+                assert!(!url.contains("0123456789"));
+                assert!(snippet.starts_with("<!DOCTYPE html> <html><head><title>Login"));
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // caught by the body even without a content type
+        assert!(matches!(
+            check(200, None, LOGIN),
+            Err(Error::HtmlResponse { .. })
+        ));
+    }
+
+    #[test]
+    fn html_content_type_without_feed() {
+        assert!(matches!(
+            check(200, Some("text/html"), b"<div>maintenance</div>"),
+            Err(Error::HtmlResponse { .. })
+        ));
+    }
+
+    #[test]
+    fn other_content_types_are_left_to_the_parser() {
+        assert!(check(200, Some("application/json"), b"{\"error\": 1}").is_ok());
+    }
+
+    #[test]
+    fn forbidden() {
+        match check(403, Some("text/html"), LOGIN) {
+            Err(e @ Error::AccessDenied { status: 403, .. }) => assert!(!e.is_retryable()),
+            x => panic!("unexpected {:?}", x),
+        }
+        assert!(matches!(
+            check(401, None, b""),
+            Err(Error::AccessDenied { status: 401, .. })
+        ));
+    }
+
+    #[test]
+    fn too_many_requests() {
+        match check(429, None, b"slow down") {
+            Err(e @ Error::RateLimited { status: 429, .. }) => assert!(e.is_retryable()),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn server_errors_are_retryable() {
+        match check(502, Some("text/html"), LOGIN) {
+            Err(e @ Error::ServerError { status: 502, .. }) => assert!(e.is_retryable()),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn other_statuses() {
+        assert!(matches!(
+            check(404, None, b"not found"),
+            Err(Error::UnexpectedStatus { status: 404, .. })
+        ));
+    }
+
+    #[test]
+    fn empty_body() {
+        assert!(matches!(
+            check(200, None, b"  \n "),
+            Err(Error::MissingBytes { .. })
+        ));
+    }
+
+    #[test]
+    fn snippet_is_short_and_redacted() {
+        let body = format! {"<p>{}</p> ?passkey=secretvalue", "x ".repeat(500)};
+        let snippet = snippet(body.as_bytes());
+        assert!(snippet.len() <= 200);
+
+        let snippet = super::snippet(b"denied for passkey=secretvalue\n\n  try again");
+        assert_eq!(snippet, "denied for passkey=REDACTED try again");
     }
 }
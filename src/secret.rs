@@ -0,0 +1,292 @@
+//!
+//! values in the config that should not have to be written out in plain text
+//!
+
+use super::redact;
+use super::Error;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// a config value that is either written inline, read from an environment
+/// variable, or read from a file (such as a docker / kubernetes secret)
+///
+/// ```yaml
+/// plain: some_value
+/// from_env: {env: TRACKER_PASSKEY}
+/// from_file: {file: /run/secrets/tracker_passkey}
+/// ```
+#[derive(Deserialize, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum Secret {
+    Plain(String),
+    Env { env: String },
+    File { file: String },
+}
+
+impl Secret {
+    /// the value, which is also masked in all output from then on
+    pub fn resolve(&self) -> Result<String, Error> {
+        let value = self.lookup()?;
+        redact::add_value(&value);
+        Ok(value)
+    }
+
+    fn lookup(&self) -> Result<String, Error> {
+        match self {
+            Secret::Plain(value) => Ok(value.clone()),
+            Secret::Env { env } => read_env(env),
+            Secret::File { file } => read_file(file),
+        }
+    }
+}
+
+fn read_env(name: &str) -> Result<String, Error> {
+    std::env::var(name).map_err(|e| Error::Secret {
+        location: format! {"environment variable {}", name},
+        reason: e.to_string(),
+    })
+}
+
+fn read_file(path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+        // files written by editors or `echo` almost always end in a newline
+        .map(|x| x.trim_end_matches(&['\r', '\n'][..]).to_string())
+        .map_err(|e| Error::Secret {
+            location: format! {"file {}", path},
+            reason: e.to_string(),
+        })
+}
+
+/// replace references to environment variables in every string of a parsed config
+/// before it is deserialized:
+///
+/// ```yaml
+/// username: ${QBIT_USERNAME}              # environment variable, which must be set
+/// address: ${QBIT_ADDRESS:-http://qbit}   # with a default when unset or empty
+/// literal: $${NOT_A_VARIABLE}             # `$${` is written out as `${`
+/// ```
+///
+/// this runs before any [`Secret`] is parsed, so the strings inside `{env: ...}` and
+/// `{file: ...}` can use references themselves. the values are not masked in output,
+/// since most of them are hosts and paths. secrets go in those forms, which are
+/// masked once they are read
+pub(crate) fn interpolate(value: &mut serde_yaml::Value) -> Result<(), Error> {
+    interpolate_at(value, &mut Vec::new())
+}
+
+// `path` is the chain of keys leading to `value`, for error messages
+fn interpolate_at(value: &mut serde_yaml::Value, path: &mut Vec<String>) -> Result<(), Error> {
+    match value {
+        serde_yaml::Value::String(string) => {
+            *string = interpolate_string(string).map_err(|e| match e {
+                Error::Secret { location, reason } => Error::Secret {
+                    location: format! {"{} (for `{}`)", location, path.join(".")},
+                    reason,
+                },
+                e => e,
+            })?;
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push(index.to_string());
+                interpolate_at(item, path)?;
+                path.pop();
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                path.push(key.as_str().unwrap_or("?").to_string());
+                interpolate_at(item, path)?;
+                path.pop();
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => interpolate_at(&mut tagged.value, path)?,
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn interpolate_string(string: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(string.len());
+    let mut rest = string;
+
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes a literal `${`
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference.find('}').ok_or_else(|| Error::Secret {
+            location: "config value".to_string(),
+            reason: "`${` is never closed with `}`".to_string(),
+        })?;
+
+        let reference = &reference[..end];
+        let value = match reference.split_once(":-") {
+            Some((name, default)) => match std::env::var(name) {
+                Ok(value) if !value.is_empty() => value,
+                _ => default.to_string(),
+            },
+            None => read_env(reference)?,
+        };
+
+        out.push_str(&value);
+        rest = &rest[start + 2 + end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+// never print the value itself, only where it comes from
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Plain(_) => write!(f, "Secret(<redacted>)"),
+            Secret::Env { env } => write!(f, "Secret(env: {})", env),
+            Secret::File { file } => write!(f, "Secret(file: {})", file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Secret {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn plain() {
+        let secret = parse("hunter22");
+        assert!(matches!(secret, Secret::Plain(_)));
+        assert_eq!(secret.resolve().unwrap(), "hunter22");
+    }
+
+    #[test]
+    fn from_env() {
+        std::env::set_var("AUTODL_TEST_SECRET_ENV", "from the environment");
+        let secret = parse("{env: AUTODL_TEST_SECRET_ENV}");
+        assert!(matches!(secret, Secret::Env { .. }));
+        assert_eq!(secret.resolve().unwrap(), "from the environment");
+    }
+
+    #[test]
+    fn missing_env() {
+        let secret = parse("{env: AUTODL_TEST_SECRET_MISSING}");
+        let error = secret.resolve().unwrap_err().to_string();
+        assert!(error.contains("environment variable AUTODL_TEST_SECRET_MISSING"));
+    }
+
+    #[test]
+    fn from_file() {
+        let path = std::env::temp_dir().join("autodl-test-secret-file");
+        std::fs::write(&path, "from a file\r\n").unwrap();
+
+        let secret = parse(&format! {"{{file: {}}}", path.display()});
+        assert!(matches!(secret, Secret::File { .. }));
+        assert_eq!(secret.resolve().unwrap(), "from a file");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let secret = parse("{file: /nonexistent/autodl-secret}");
+        let error = secret.resolve().unwrap_err().to_string();
+        assert!(error.contains("file /nonexistent/autodl-secret"));
+    }
+
+    #[test]
+    fn unknown_map_is_rejected() {
+        assert!(serde_yaml::from_str::<Secret>("{vault: x}").is_err());
+    }
+
+    #[test]
+    fn references_are_filled_in() {
+        std::env::set_var("AUTODL_TEST_INTERPOLATE_HOST", "interpolated.example");
+        std::env::set_var("AUTODL_TEST_INTERPOLATE_EMPTY", "");
+
+        let mut value = serde_yaml::from_str(
+            "url: https://${AUTODL_TEST_INTERPOLATE_HOST}/rss\n\
+             address: ${AUTODL_TEST_INTERPOLATE_UNSET:-http://localhost:8080}\n\
+             empty: ${AUTODL_TEST_INTERPOLATE_EMPTY:-fallback}\n\
+             literal: $${AUTODL_TEST_INTERPOLATE_HOST}",
+        )
+        .unwrap();
+        interpolate(&mut value).unwrap();
+
+        assert_eq!(value["url"], "https://interpolated.example/rss");
+        assert_eq!(value["address"], "http://localhost:8080");
+        assert_eq!(value["empty"], "fallback");
+        assert_eq!(value["literal"], "${AUTODL_TEST_INTERPOLATE_HOST}");
+    }
+
+    #[test]
+    fn interpolated_values_are_not_redacted() {
+        std::env::set_var(
+            "AUTODL_TEST_INTERPOLATE_PLAIN",
+            "plain.interpolated.example",
+        );
+
+        let mut value =
+            serde_yaml::from_str("url: https://${AUTODL_TEST_INTERPOLATE_PLAIN}/rss").unwrap();
+        interpolate(&mut value).unwrap();
+
+        let url = value["url"].as_str().unwrap();
+        assert_eq!(redact::text(url), "https://plain.interpolated.example/rss");
+    }
+
+    #[test]
+    fn file_urls_are_left_alone() {
+        let mut value = serde_yaml::from_str("link: file:///downloads/list.txt").unwrap();
+        interpolate(&mut value).unwrap();
+        assert_eq!(value["link"], "file:///downloads/list.txt");
+    }
+
+    #[test]
+    fn missing_references_name_the_setting() {
+        let mut value =
+            serde_yaml::from_str("qbittorrent:\n  username: ${AUTODL_TEST_INTERPOLATE_MISSING}")
+                .unwrap();
+        let error = interpolate(&mut value).unwrap_err().to_string();
+        assert!(error.contains("AUTODL_TEST_INTERPOLATE_MISSING"));
+        assert!(error.contains("qbittorrent.username"));
+    }
+
+    #[test]
+    fn interpolation_runs_inside_secret_forms() {
+        std::env::set_var(
+            "AUTODL_TEST_INTERPOLATE_NAME",
+            "AUTODL_TEST_INTERPOLATE_TARGET",
+        );
+        std::env::set_var("AUTODL_TEST_INTERPOLATE_TARGET", "the real secret");
+
+        let mut value = serde_yaml::from_str("env: ${AUTODL_TEST_INTERPOLATE_NAME}").unwrap();
+        interpolate(&mut value).unwrap();
+        let secret: Secret = serde_yaml::from_value(value).unwrap();
+        assert_eq!(secret.resolve().unwrap(), "the real secret");
+
+        // the secret is masked once it is used, the variable holding its name is not
+        assert_eq!(redact::text("the real secret"), "REDACTED");
+        assert_eq!(
+            redact::text("AUTODL_TEST_INTERPOLATE_TARGET"),
+            "AUTODL_TEST_INTERPOLATE_TARGET"
+        );
+    }
+
+    #[test]
+    fn debug_hides_plain_values() {
+        let debug = format! {"{:?}", parse("hunter22")};
+        assert!(!debug.contains("hunter22"));
+        assert_eq!(format! {"{:?}", parse("{env: NAME}")}, "Secret(env: NAME)");
+    }
+}
@@ -0,0 +1,674 @@
+//!
+//! optional http server for monitoring the daemon
+//!
+
+use std::net::SocketAddr;
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+
+use super::control::{Controller, FeedControl, ItemView};
+use super::history::{History, HistoryEntry};
+use super::metrics::Metrics;
+use super::qbit_api::QbitApi;
+use super::redact;
+use super::secret::Secret;
+use super::status::{FeedReport, HealthConfig, Report, Status};
+use super::supervisor::Shutdown;
+use super::torrents::{ExemptReason, ExemptTorrent, PausedTorrent, TorrentTable};
+use super::yaml::TorrentMatch;
+use super::Error;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct ServerConfig {
+    /// address to listen on, such as 0.0.0.0:9090
+    pub listen: SocketAddr,
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// when set, requests to /api must send `Authorization: Bearer <token>`. without
+    /// it /api is only served when `listen` is a loopback address
+    #[serde(default)]
+    pub api_token: Option<Secret>,
+    /// folders that matchers added or replaced through /api may save into. the save
+    /// folders of the matchers in the config by default
+    #[serde(default)]
+    pub save_roots: Vec<PathBuf>,
+}
+
+impl ServerConfig {
+    /// whether /api can be served, which needs a token unless only this machine can
+    /// reach it
+    pub fn api_enabled(&self) -> bool {
+        self.api_token.is_some() || self.listen.ip().is_loopback()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerState {
+    pub metrics: Arc<Metrics>,
+    pub status: Arc<Status>,
+    pub health: HealthConfig,
+    pub control: Arc<Controller>,
+    pub history: Arc<History>,
+    pub torrents: Arc<TorrentTable>,
+    pub qbit_api: Arc<QbitApi>,
+    pub api_token: Option<Secret>,
+    pub save_roots: Arc<Vec<PathBuf>>,
+}
+
+// the web ui is a single page talking to the api below
+const INDEX: &str = include_str!("../static/index.html");
+
+/// every route, with /api left out unless `api` is set
+pub fn router(state: ServerState, api: bool) -> Router {
+    let routes = Router::new()
+        .route("/", get(index))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    if !api {
+        return routes.with_state(state);
+    }
+
+    let api = Router::new()
+        .route("/feeds", get(list_feeds))
+        .route("/feeds/:id/poll", post(poll_feed))
+        .route("/feeds/:id/pause", post(pause_feed))
+        .route("/feeds/:id/resume", post(resume_feed))
+        .route("/feeds/:id/matchers", get(list_matchers).post(add_matcher))
+        .route(
+            "/feeds/:id/matchers/:index",
+            put(update_matcher).delete(remove_matcher),
+        )
+        .route("/feeds/:id/retry/:item_hash", post(retry_item))
+        .route("/feeds/:id/items", get(list_items))
+        .route("/feeds/:id/preview", post(preview_matcher))
+        .route("/history", get(history))
+        .route("/paused", get(list_paused))
+        .route("/paused/:hash/resume", post(resume_paused))
+        .route("/exempt", get(list_exempt))
+        .route("/exempt/:hash", put(exempt).delete(clear_exemption))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+
+    routes.nest("/api", api).with_state(state)
+}
+
+/// serve `router` on `config.listen` in the background until shutdown
+pub fn spawn(
+    config: &ServerConfig,
+    state: ServerState,
+    shutdown: Shutdown,
+) -> Result<JoinHandle<()>, Error> {
+    let api = config.api_enabled();
+    if !api {
+        println! {"not serving /api on {} without an `api_token`", config.listen};
+    }
+
+    let server = axum::Server::try_bind(&config.listen)?
+        .serve(router(state, api).into_make_service())
+        .with_graceful_shutdown(async move { shutdown.wait().await });
+    println! {"serving http on {}", config.listen};
+
+    Ok(tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println! {"http server stopped: {}", e};
+        }
+    }))
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX)
+}
+
+async fn metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+async fn healthz(State(state): State<ServerState>) -> impl IntoResponse {
+    health_response(state.status.liveness(&state.health))
+}
+
+async fn readyz(State(state): State<ServerState>) -> impl IntoResponse {
+    health_response(state.status.readiness(&state.health))
+}
+
+fn health_response(report: Report) -> impl IntoResponse {
+    let code = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (code, Json(report))
+}
+
+/// an [`Error`] turned into a json response for the api
+struct ApiError(Error);
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        Self(error)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let code = match &self.0 {
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::SaveFolder(_) => StatusCode::FORBIDDEN,
+            Error::DuplicateName { .. } => StatusCode::CONFLICT,
+            Error::TaskStopped(_) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let body = ErrorBody {
+            error: redact::text(&self.0.to_string()),
+        };
+        (code, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+async fn authorize<B>(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let token = match &state.api_token {
+        Some(token) => token,
+        None => return next.run(request).await,
+    };
+
+    let token = match token.resolve() {
+        Ok(token) => token,
+        Err(e) => return ApiError(e).into_response(),
+    };
+
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+
+    let allowed = given.is_some_and(|x| constant_time_eq(x.as_bytes(), token.as_bytes()));
+    if allowed {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+// look at every byte even after a difference, so that the time taken does not tell
+// how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// matchers from the api can name any folder, which the monitor creates before adding
+// the torrent, so they are kept to the configured roots
+fn check_save_folder(roots: &[PathBuf], matcher: &TorrentMatch) -> Result<(), Error> {
+    let folder = std::path::Path::new(&matcher.save_folder);
+    let escapes = folder.components().any(|x| x == Component::ParentDir);
+
+    if !escapes && roots.iter().any(|x| folder.starts_with(x)) {
+        Ok(())
+    } else {
+        Err(Error::SaveFolder(matcher.save_folder.clone()))
+    }
+}
+
+// a matcher sent to the api. one that does not parse is the client's mistake, answered
+// with the same json error as everything else
+fn matcher_body(body: Result<Json<TorrentMatch>, JsonRejection>) -> ApiResult<TorrentMatch> {
+    match body {
+        Ok(Json(matcher)) => Ok(matcher),
+        Err(e) => Err(Error::BadRequest(e.body_text()).into()),
+    }
+}
+
+#[derive(Serialize)]
+struct FeedView {
+    id: usize,
+    name: String,
+    url: String,
+    paused: bool,
+    status: Option<FeedReport>,
+}
+
+async fn list_feeds(State(state): State<ServerState>) -> Json<Vec<FeedView>> {
+    let mut reports = state.status.readiness(&state.health).feeds;
+
+    let feeds = state
+        .control
+        .feeds()
+        .iter()
+        .map(|feed| {
+            let status = reports
+                .iter()
+                .position(|x| x.feed == feed.name)
+                .map(|x| reports.remove(x));
+
+            FeedView {
+                id: feed.id,
+                name: feed.name.clone(),
+                url: redact::url(&feed.url),
+                paused: feed.is_paused(),
+                status,
+            }
+        })
+        .collect();
+
+    Json(feeds)
+}
+
+fn feed(state: &ServerState, id: usize) -> ApiResult<&FeedControl> {
+    Ok(state.control.feed(id)?)
+}
+
+async fn poll_feed(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+) -> ApiResult<StatusCode> {
+    feed(&state, id)?.poll().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_feed(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+) -> ApiResult<StatusCode> {
+    feed(&state, id)?.pause();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resume_feed(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+) -> ApiResult<StatusCode> {
+    feed(&state, id)?.resume();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_matchers(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+) -> ApiResult<Json<Vec<TorrentMatch>>> {
+    Ok(Json(feed(&state, id)?.matchers().await?))
+}
+
+#[derive(Serialize)]
+struct Created {
+    index: usize,
+}
+
+async fn add_matcher(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+    matcher: Result<Json<TorrentMatch>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Created>)> {
+    let matcher = matcher_body(matcher)?;
+    check_save_folder(&state.save_roots, &matcher)?;
+    let index = feed(&state, id)?.add_matcher(matcher).await?;
+    Ok((StatusCode::CREATED, Json(Created { index })))
+}
+
+async fn update_matcher(
+    State(state): State<ServerState>,
+    Path((id, index)): Path<(usize, usize)>,
+    matcher: Result<Json<TorrentMatch>, JsonRejection>,
+) -> ApiResult<StatusCode> {
+    let matcher = matcher_body(matcher)?;
+    check_save_folder(&state.save_roots, &matcher)?;
+    feed(&state, id)?.update_matcher(index, matcher).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_matcher(
+    State(state): State<ServerState>,
+    Path((id, index)): Path<(usize, usize)>,
+) -> ApiResult<Json<TorrentMatch>> {
+    Ok(Json(feed(&state, id)?.remove_matcher(index).await?))
+}
+
+async fn retry_item(
+    State(state): State<ServerState>,
+    Path((id, item_hash)): Path<(usize, u64)>,
+) -> ApiResult<StatusCode> {
+    feed(&state, id)?.retry(item_hash).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn history(State(state): State<ServerState>) -> Json<Vec<HistoryEntry>> {
+    Json(state.history.recent())
+}
+
+async fn list_items(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+) -> ApiResult<Json<Vec<ItemView>>> {
+    Ok(Json(feed(&state, id)?.items().await?))
+}
+
+async fn preview_matcher(
+    State(state): State<ServerState>,
+    Path(id): Path<usize>,
+    matcher: Result<Json<TorrentMatch>, JsonRejection>,
+) -> ApiResult<Json<Vec<ItemView>>> {
+    let matcher = matcher_body(matcher)?;
+    Ok(Json(feed(&state, id)?.preview(matcher).await?))
+}
+
+async fn list_paused(State(state): State<ServerState>) -> Json<Vec<PausedTorrent>> {
+    Json(state.torrents.list_paused())
+}
+
+async fn resume_paused(
+    State(state): State<ServerState>,
+    Path(hash): Path<String>,
+) -> ApiResult<Json<PausedTorrent>> {
+    Ok(Json(state.torrents.undo(&state.qbit_api, &hash).await?))
+}
+
+async fn list_exempt(State(state): State<ServerState>) -> Json<Vec<ExemptTorrent>> {
+    Json(state.torrents.list_exempt())
+}
+
+async fn exempt(
+    State(state): State<ServerState>,
+    Path(hash): Path<String>,
+) -> ApiResult<StatusCode> {
+    state.torrents.exempt(&hash, ExemptReason::Api)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn clear_exemption(
+    State(state): State<ServerState>,
+    Path(hash): Path<String>,
+) -> ApiResult<StatusCode> {
+    state.torrents.clear_exemption(&hash)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(listen: &str, token: bool) -> ServerConfig {
+        let token = if token { "\napi_token: secret123" } else { "" };
+        serde_yaml::from_str(&format! {"listen: {}{}", listen, token}).unwrap()
+    }
+
+    #[test]
+    fn api_needs_a_token_unless_on_loopback() {
+        assert!(config("127.0.0.1:9090", false).api_enabled());
+        assert!(config("'[::1]:9090'", false).api_enabled());
+        assert!(!config("0.0.0.0:9090", false).api_enabled());
+        assert!(config("0.0.0.0:9090", true).api_enabled());
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(constant_time_eq(b"secret123", b"secret123"));
+        assert!(!constant_time_eq(b"secret123", b"secret124"));
+        assert!(!constant_time_eq(b"secret", b"secret123"));
+        assert!(!constant_time_eq(b"", b"secret123"));
+    }
+
+    #[test]
+    fn save_folders_stay_in_their_roots() {
+        let roots = [PathBuf::from("/downloads"), PathBuf::from("media/shows")];
+        let check = |folder: &str| {
+            let matcher =
+                serde_yaml::from_str::<TorrentMatch>(&format! {"save_folder: {}", folder}).unwrap();
+            check_save_folder(&roots, &matcher).is_ok()
+        };
+
+        assert!(check("/downloads"));
+        assert!(check("/downloads/shows/new"));
+        assert!(check("media/shows/new"));
+        assert!(!check("/downloads-other"));
+        assert!(!check("/downloads/../etc"));
+        assert!(!check("/etc/cron.d"));
+        assert!(!check("media"));
+    }
+
+    // what the fake feed task behind the api was asked to do
+    #[derive(Debug, Default)]
+    struct FakeFeed {
+        matchers: Vec<TorrentMatch>,
+        polls: usize,
+        retried: Vec<u64>,
+    }
+
+    type Shared = Arc<std::sync::Mutex<FakeFeed>>;
+
+    // answer the commands of a feed task from `fake`, the way the real task does
+    fn fake_feed_task(mut commands: crate::control::FeedCommands, fake: Shared) {
+        use crate::control::FeedCommand;
+
+        tokio::spawn(async move {
+            while let Some(command) = commands.receiver.recv().await {
+                let mut fake = fake.lock().unwrap();
+                let missing = |index| Error::NotFound(format! {"matcher {}", index});
+                match command {
+                    FeedCommand::Poll(reply) => {
+                        fake.polls += 1;
+                        let _ = reply.send(Ok(()));
+                    }
+                    FeedCommand::ListMatchers(reply) => {
+                        let _ = reply.send(Ok(fake.matchers.clone()));
+                    }
+                    FeedCommand::AddMatcher(matcher, reply) => {
+                        fake.matchers.push(matcher);
+                        let _ = reply.send(Ok(fake.matchers.len() - 1));
+                    }
+                    FeedCommand::UpdateMatcher(index, matcher, reply) => {
+                        let result = match fake.matchers.get_mut(index) {
+                            Some(existing) => {
+                                *existing = matcher;
+                                Ok(())
+                            }
+                            None => Err(missing(index)),
+                        };
+                        let _ = reply.send(result);
+                    }
+                    FeedCommand::RemoveMatcher(index, reply) => {
+                        let result = if index < fake.matchers.len() {
+                            Ok(fake.matchers.remove(index))
+                        } else {
+                            Err(missing(index))
+                        };
+                        let _ = reply.send(result);
+                    }
+                    FeedCommand::Retry(item_hash, reply) => {
+                        fake.retried.push(item_hash);
+                        let _ = reply.send(Ok(()));
+                    }
+                    FeedCommand::Items(reply) | FeedCommand::Preview(_, reply) => {
+                        let _ = reply.send(Ok(Vec::new()));
+                    }
+                }
+            }
+        });
+    }
+
+    // a qbittorrent that only accepts the login, which is all the api needs to start
+    async fn qbit_api() -> QbitApi {
+        let router = Router::new().route("/api/v2/auth/login", post(|| async { "Ok." }));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        let auth = serde_yaml::from_str(&format! {
+            "username: admin\npassword: adminadmin\naddress: http://{}\ntrackers: []",
+            address
+        })
+        .unwrap();
+        QbitApi::new(&auth).await.unwrap()
+    }
+
+    // the full router with one feed, id 0, answered by `fake`
+    async fn api(fake: &Shared) -> (Router, FeedControl) {
+        let (control, commands) = crate::control::channel(0, "tracker", "https://t.example/rss");
+        fake_feed_task(commands, Arc::clone(fake));
+
+        let state = ServerState {
+            metrics: Arc::new(Metrics::new()),
+            status: Arc::new(Status::new()),
+            health: HealthConfig::default(),
+            control: Arc::new(Controller::new(vec![control.clone()])),
+            history: Arc::new(History::new()),
+            torrents: Arc::new(TorrentTable::default()),
+            qbit_api: Arc::new(qbit_api().await),
+            api_token: None,
+            save_roots: Arc::new(vec![PathBuf::from("/downloads")]),
+        };
+        (router(state, true), control)
+    }
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        use tower::ServiceExt;
+
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(body.unwrap_or_default().to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn feeds_are_polled_paused_and_resumed() {
+        let fake = Shared::default();
+        let (router, control) = api(&fake).await;
+
+        let (status, feeds) = send(&router, "GET", "/api/feeds", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(feeds[0]["name"], "tracker");
+        assert_eq!(feeds[0]["paused"], false);
+
+        let (status, _) = send(&router, "POST", "/api/feeds/0/pause", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(control.is_paused());
+        let (_, feeds) = send(&router, "GET", "/api/feeds", None).await;
+        assert_eq!(feeds[0]["paused"], true);
+
+        let (status, _) = send(&router, "POST", "/api/feeds/0/resume", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!control.is_paused());
+
+        let (status, _) = send(&router, "POST", "/api/feeds/0/poll", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(fake.lock().unwrap().polls, 1);
+
+        let (status, _) = send(&router, "POST", "/api/feeds/0/retry/42", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(fake.lock().unwrap().retried, vec![42]);
+    }
+
+    #[tokio::test]
+    async fn matchers_are_listed_added_replaced_and_removed() {
+        let fake = Shared::default();
+        let (router, _) = api(&fake).await;
+        let shows = r#"{"name": "shows", "save_folder": "/downloads/shows"}"#;
+        let movies = r#"{"name": "movies", "save_folder": "/downloads/movies"}"#;
+
+        let (status, created) = send(&router, "POST", "/api/feeds/0/matchers", Some(shows)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["index"], 0);
+
+        let (status, _) = send(&router, "PUT", "/api/feeds/0/matchers/0", Some(movies)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, matchers) = send(&router, "GET", "/api/feeds/0/matchers", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(matchers.as_array().unwrap().len(), 1);
+        assert_eq!(matchers[0]["name"], "movies");
+
+        // a matcher that is not there
+        let (status, body) = send(&router, "PUT", "/api/feeds/0/matchers/3", Some(shows)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "matcher 3 does not exist");
+
+        let (status, removed) = send(&router, "DELETE", "/api/feeds/0/matchers/0", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(removed["save_folder"], "/downloads/movies");
+        assert!(fake.lock().unwrap().matchers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_feeds_are_not_found() {
+        let fake = Shared::default();
+        let (router, _) = api(&fake).await;
+
+        for (method, uri) in [
+            ("POST", "/api/feeds/7/poll"),
+            ("POST", "/api/feeds/7/pause"),
+            ("GET", "/api/feeds/7/matchers"),
+            ("DELETE", "/api/feeds/7/matchers/0"),
+            ("POST", "/api/feeds/7/retry/42"),
+        ] {
+            let (status, body) = send(&router, method, uri, None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+            assert_eq!(body["error"], "feed 7 does not exist");
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_matchers_are_rejected() {
+        let fake = Shared::default();
+        let (router, _) = api(&fake).await;
+
+        for body in [
+            "{\"name\": \"shows\"",
+            r#"{"name": "shows"}"#,
+            r#"{"save_folder": "/downloads", "tags_wanted": "1080p"}"#,
+        ] {
+            let (status, error) = send(&router, "POST", "/api/feeds/0/matchers", Some(body)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+            assert!(error["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("invalid request"));
+        }
+
+        // parses, but saves somewhere it may not
+        let outside = r#"{"save_folder": "/etc"}"#;
+        let (status, _) = send(&router, "POST", "/api/feeds/0/matchers", Some(outside)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(fake.lock().unwrap().matchers.is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+//!
+//! shared view of how every part of the daemon is doing, for health checks
+//!
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::backoff::{FeedHealth, HealthState};
+use super::redact;
+use super::Error;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct HealthConfig {
+    /// feeds allowed to be failing before the daemon reports as not ready
+    #[serde(default)]
+    pub max_failing_feeds: usize,
+    /// seconds the qbittorrent loop may go without running before the daemon is
+    /// considered dead
+    #[serde(default = "default_max_loop_age")]
+    pub max_loop_age: u64,
+    /// seconds a feed may be overdue for its next poll before its task is considered dead
+    #[serde(default = "default_feed_grace")]
+    pub feed_grace: u64,
+}
+
+fn default_max_loop_age() -> u64 {
+    300
+}
+fn default_feed_grace() -> u64 {
+    300
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_failing_feeds: 0,
+            max_loop_age: default_max_loop_age(),
+            feed_grace: default_feed_grace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FeedStatus {
+    health: Option<FeedHealth>,
+    next_poll: Option<SystemTime>,
+    // set by the supervisor, false while a panicked task waits to be restarted
+    task_running: bool,
+    // paused through the api, so it is not polling on purpose
+    paused: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct QbitStatus {
+    task_running: bool,
+    last_loop: Option<SystemTime>,
+    last_success: Option<SystemTime>,
+    last_error: Option<String>,
+    // result of the last maindata sync, which is what tells if qbittorrent is reachable
+    last_sync: Option<SystemTime>,
+    sync_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Status {
+    feeds: RwLock<BTreeMap<String, FeedStatus>>,
+    qbit: RwLock<QbitStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub healthy: bool,
+    pub problems: Vec<String>,
+    pub monitor_loop: LoopReport,
+    pub feeds: Vec<FeedReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoopReport {
+    pub running: bool,
+    pub last_loop: Option<u64>,
+    pub qbittorrent_connected: bool,
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedReport {
+    pub feed: String,
+    pub state: Option<HealthState>,
+    pub task_running: bool,
+    pub paused: bool,
+    pub consecutive_failures: u32,
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    pub next_poll: Option<u64>,
+}
+
+fn unix(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs())
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// make a feed show up in reports before its first poll finishes
+    pub fn register_feed(&self, feed: &str) {
+        self.feeds
+            .write()
+            .expect("status lock poisoned")
+            .entry(feed.to_string())
+            .or_default();
+    }
+
+    /// record whether the task of a feed is running, as seen by its supervisor
+    pub fn feed_task(&self, feed: &str, running: bool) {
+        let mut feeds = self.feeds.write().expect("status lock poisoned");
+        feeds.entry(feed.to_string()).or_default().task_running = running;
+    }
+
+    /// record whether the qbittorrent loop is running, as seen by its supervisor
+    pub fn qbit_task(&self, running: bool) {
+        self.qbit
+            .write()
+            .expect("status lock poisoned")
+            .task_running = running;
+    }
+
+    /// record the result of syncing the torrent list with qbittorrent
+    pub fn qbit_synced(&self, error: Option<&Error>) {
+        let mut qbit = self.qbit.write().expect("status lock poisoned");
+        match error {
+            Some(e) => qbit.sync_error = Some(redact::text(&e.to_string())),
+            None => {
+                qbit.last_sync = Some(SystemTime::now());
+                qbit.sync_error = None;
+            }
+        }
+    }
+
+    pub fn update_feed(&self, feed: &str, health: &FeedHealth, wait: Duration) {
+        let mut feeds = self.feeds.write().expect("status lock poisoned");
+        let status = feeds.entry(feed.to_string()).or_default();
+        status.health = Some(health.clone());
+        status.next_poll = Some(SystemTime::now() + wait);
+        status.paused = false;
+    }
+
+    /// record that a paused feed skipped its poll, and when it looks again
+    pub fn feed_paused(&self, feed: &str, wait: Duration) {
+        let mut feeds = self.feeds.write().expect("status lock poisoned");
+        let status = feeds.entry(feed.to_string()).or_default();
+        status.next_poll = Some(SystemTime::now() + wait);
+        status.paused = true;
+    }
+
+    /// record one pass of the qbittorrent loop and the errors it ran into
+    pub fn qbit_checked(&self, errors: Vec<String>) {
+        let mut qbit = self.qbit.write().expect("status lock poisoned");
+        let now = SystemTime::now();
+        qbit.last_loop = Some(now);
+
+        if errors.is_empty() {
+            qbit.last_success = Some(now);
+            qbit.last_error = None;
+        } else {
+            qbit.last_error = Some(redact::text(&errors.join("; ")));
+        }
+    }
+
+    /// liveness: the qbittorrent loop and every feed task are still running
+    pub fn liveness(&self, config: &HealthConfig) -> Report {
+        let mut report = self.report(config);
+
+        let mut problems = Vec::new();
+        if !report.monitor_loop.running {
+            problems.push("qbittorrent monitor loop is not running".to_string());
+        }
+        for feed in report.feeds.iter().filter(|x| !x.task_running) {
+            problems.push(format! {"feed task for {} is not running", feed.feed});
+        }
+
+        report.healthy = problems.is_empty();
+        report.problems = problems;
+        report
+    }
+
+    /// readiness: everything is running, qbittorrent is reachable and no more feeds
+    /// are failing than allowed
+    pub fn readiness(&self, config: &HealthConfig) -> Report {
+        self.report(config)
+    }
+
+    fn report(&self, config: &HealthConfig) -> Report {
+        let now = SystemTime::now();
+        let mut problems = Vec::new();
+
+        let qbit = self.qbit.read().expect("status lock poisoned").clone();
+        let running = qbit.task_running
+            && match qbit.last_loop {
+                Some(last) => {
+                    now.duration_since(last).unwrap_or_default()
+                        <= Duration::from_secs(config.max_loop_age)
+                }
+                // the first pass has not finished yet
+                None => true,
+            };
+        if !running {
+            problems.push("qbittorrent monitor loop is not running".to_string());
+        }
+
+        // not ready until qbittorrent has answered a sync. other failures of the loop,
+        // such as saving the torrent states, say nothing about the connection
+        let connected = qbit.last_sync.is_some() && qbit.sync_error.is_none();
+        if !connected {
+            problems.push("qbittorrent is not reachable".to_string());
+        }
+
+        let grace = Duration::from_secs(config.feed_grace);
+        let feeds = self
+            .feeds
+            .read()
+            .expect("status lock poisoned")
+            .iter()
+            .map(|(feed, status)| {
+                // a task that is alive but long overdue for its poll is stuck, unless it
+                // was paused and is not polling at all
+                let task_running = status.task_running
+                    && (status.paused
+                        || match status.next_poll {
+                            Some(next) => now.duration_since(next).unwrap_or_default() <= grace,
+                            None => true,
+                        });
+                if !task_running {
+                    problems.push(format! {"feed task for {} is not running", feed});
+                }
+
+                let health = status.health.as_ref();
+                FeedReport {
+                    feed: feed.clone(),
+                    state: health.map(|x| x.state),
+                    task_running,
+                    paused: status.paused,
+                    consecutive_failures: health.map(|x| x.consecutive_failures).unwrap_or(0),
+                    last_success: unix(health.and_then(|x| x.last_success)),
+                    last_error: health.and_then(|x| x.last_error.clone()),
+                    next_poll: unix(status.next_poll),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let failing = feeds
+            .iter()
+            .filter(|x| x.state == Some(HealthState::Failing))
+            .count();
+        if failing > config.max_failing_feeds {
+            problems.push(format! {"{} feeds are failing", failing});
+        }
+
+        Report {
+            healthy: problems.is_empty(),
+            problems,
+            monitor_loop: LoopReport {
+                running,
+                last_loop: unix(qbit.last_loop),
+                qbittorrent_connected: connected,
+                last_success: unix(qbit.last_success),
+                last_error: qbit.last_error,
+            },
+            feeds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_tasks_are_alive_only_while_supervised() {
+        let status = Status::new();
+        let config = HealthConfig::default();
+        status.qbit_task(true);
+
+        // registered, but the task never started
+        status.register_feed("tracker");
+        assert!(!status.liveness(&config).healthy);
+
+        // started and not polled yet
+        status.feed_task("tracker", true);
+        assert!(status.liveness(&config).healthy);
+
+        // died before its first poll
+        status.feed_task("tracker", false);
+        let report = status.liveness(&config);
+        assert_eq!(report.problems, ["feed task for tracker is not running"]);
+    }
+
+    #[test]
+    fn paused_feeds_are_never_overdue() {
+        let status = Status::new();
+        let config = HealthConfig {
+            feed_grace: 0,
+            ..HealthConfig::default()
+        };
+        status.qbit_task(true);
+        status.feed_task("tracker", true);
+
+        // polled, and then nothing happened for longer than the grace period
+        let health = FeedHealth::new(Default::default());
+        status.update_feed("tracker", &health, Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(!status.liveness(&config).healthy);
+
+        status.feed_paused("tracker", Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(10));
+        let report = status.liveness(&config);
+        assert!(report.healthy);
+        assert!(report.feeds[0].paused);
+
+        // resumed and overdue again
+        status.update_feed("tracker", &health, Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(!status.liveness(&config).healthy);
+    }
+
+    #[test]
+    fn connection_follows_the_sync_only() {
+        let status = Status::new();
+        let config = HealthConfig::default();
+        status.qbit_task(true);
+        assert!(!status.readiness(&config).monitor_loop.qbittorrent_connected);
+
+        status.qbit_synced(None);
+        status.qbit_checked(vec!["could not save torrent states".to_string()]);
+        let report = status.readiness(&config);
+        assert!(report.monitor_loop.qbittorrent_connected);
+        assert!(report.healthy);
+
+        status.qbit_synced(Some(&Error::NotFound("sync/maindata".to_string())));
+        assert!(!status.readiness(&config).monitor_loop.qbittorrent_connected);
+
+        // a loop that is not running is not alive, whenever it last ran
+        status.qbit_task(false);
+        assert!(!status.liveness(&config).monitor_loop.running);
+    }
+}
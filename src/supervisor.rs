@@ -0,0 +1,234 @@
+//!
+//! restarting tasks that panic, and stopping every task on shutdown
+//!
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use super::backoff;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+// seconds before the first restart of a panicked task, doubling up to the max
+const RESTART_BASE_DELAY: u64 = 1;
+const RESTART_MAX_DELAY: u64 = 5 * 60;
+// a task that ran this long before panicking starts its backoff over
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// tells every task that the daemon is stopping
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: std::sync::Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self {
+            sender: std::sync::Arc::new(sender),
+            receiver,
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// resolves once shutdown has been triggered
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow_and_update() {
+            // the sender lives as long as `self`, so this cannot fail
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// run the future made by `make` until it returns, making a new one whenever it
+/// panics. a task that returns on its own is not restarted. `running` is told
+/// whenever the task starts or stops, for health checks
+pub fn supervise<F, Fut, R>(
+    name: String,
+    shutdown: Shutdown,
+    running: R,
+    mut make: F,
+) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+    R: Fn(bool) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut failures = 0;
+
+        loop {
+            let started = Instant::now();
+            running(true);
+            let result = tokio::spawn(make()).await;
+            running(false);
+
+            let error = match result {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+
+            if !error.is_panic() {
+                println! {"task {} was cancelled", name};
+                break;
+            }
+
+            if started.elapsed() >= RESTART_RESET_AFTER {
+                failures = 0;
+            }
+            failures += 1;
+
+            let message = error.into_panic();
+            let message = message
+                .downcast_ref::<&str>()
+                .map(|x| x.to_string())
+                .or_else(|| message.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+
+            let wait = backoff::delay(RESTART_BASE_DELAY, RESTART_MAX_DELAY, failures);
+            println! {"task {} panicked, restarting in {}s: {}", name, wait.as_secs(), message};
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.wait() => break,
+            }
+        }
+    })
+}
+
+/// wait for SIGINT or SIGTERM
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                println! {"could not listen for SIGTERM: {}", e};
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// wait for `tasks` to finish until `timeout` has passed. returns the number of
+/// tasks that were still running
+pub async fn join_all(tasks: Vec<JoinHandle<()>>, timeout: Duration) -> usize {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut unfinished = 0;
+
+    for task in tasks {
+        if tokio::time::timeout_at(deadline, task).await.is_err() {
+            unfinished += 1;
+        }
+    }
+
+    unfinished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test(start_paused = true)]
+    async fn panicked_tasks_are_restarted_after_a_backoff() {
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = {
+            let starts = Arc::clone(&starts);
+            let running = Arc::clone(&running);
+            supervise(
+                "test".to_string(),
+                Shutdown::new(),
+                move |x| running.lock().unwrap().push(x),
+                move || {
+                    let starts = Arc::clone(&starts);
+                    async move {
+                        let run = {
+                            let mut starts = starts.lock().unwrap();
+                            starts.push(tokio::time::Instant::now());
+                            starts.len()
+                        };
+                        // the third run returns, which ends the task for good
+                        if run < 3 {
+                            panic!("run {} failed", run);
+                        }
+                    }
+                },
+            )
+        };
+        handle.await.unwrap();
+
+        let starts = starts.lock().unwrap();
+        assert_eq!(starts.len(), 3);
+        // half of each delay is fixed, and it doubles with every panic in a row
+        let first = starts[1] - starts[0];
+        let second = starts[2] - starts[1];
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+        assert!(second >= Duration::from_secs(1) && second <= Duration::from_secs(2));
+
+        assert_eq!(
+            *running.lock().unwrap(),
+            vec![true, false, true, false, true, false]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_restarts() {
+        let shutdown = Shutdown::new();
+        let (started, mut starts) = tokio::sync::mpsc::unbounded_channel();
+
+        let handle = supervise(
+            "test".to_string(),
+            shutdown.clone(),
+            |_| (),
+            move || {
+                let started = started.clone();
+                async move {
+                    let _ = started.send(());
+                    panic!("always fails");
+                }
+            },
+        );
+
+        // stop while the task waits to be restarted
+        starts.recv().await.unwrap();
+        shutdown.trigger();
+        handle.await.unwrap();
+
+        assert!(starts.try_recv().is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//!
+//! what the qbittorrent monitor knows about each torrent, kept across restarts
+//!
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::monitor::BANNED_TITLE_TAG;
+use super::qbit_api::QbitApi;
+use super::Error;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseReason {
+    // none of the torrent's trackers are in `qbittorrent.trackers`
+    Tracker,
+    // the torrent's name contains one of `qbittorrent.title_bans`
+    Title,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentState {
+    pub name: String,
+    // unix time in seconds
+    pub first_seen: u64,
+    // why we paused the torrent, and when
+    #[serde(default)]
+    pub paused: BTreeMap<PauseReason, u64>,
+    // set once the torrent should never be touched again
+    #[serde(default)]
+    pub exempt: Option<Exemption>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExemptReason {
+    // a torrent we paused was resumed in qbittorrent by hand
+    ResumedByHand,
+    // our pause was undone from the web ui
+    Undone,
+    // exempted through the api
+    Api,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Exemption {
+    pub reason: ExemptReason,
+    // unix time in seconds
+    pub time: u64,
+}
+
+/// an exempt torrent, as shown by the api
+#[derive(Debug, Clone, Serialize)]
+pub struct ExemptTorrent {
+    pub hash: String,
+    pub name: String,
+    pub reason: ExemptReason,
+    pub time: u64,
+}
+
+/// a torrent we paused, as shown in the web ui
+#[derive(Debug, Clone, Serialize)]
+pub struct PausedTorrent {
+    pub hash: String,
+    pub name: String,
+    pub reason: PauseReason,
+    // unix time in seconds
+    pub time: u64,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    torrents: BTreeMap<String, TorrentState>,
+    // changed since the last save
+    dirty: bool,
+}
+
+/// per-torrent state keyed by info-hash
+#[derive(Debug, Default)]
+pub struct TorrentTable {
+    table: RwLock<Table>,
+    // where the table is saved, kept in memory only when missing
+    path: Option<PathBuf>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+impl TorrentTable {
+    /// read the table saved at `path`, starting empty if there is none
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Error> {
+        let torrents = match &path {
+            Some(path) => match std::fs::File::open(path) {
+                Ok(file) => serde_json::from_reader(std::io::BufReader::new(file))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(e.into()),
+            },
+            None => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            table: RwLock::new(Table {
+                torrents,
+                dirty: false,
+            }),
+            path,
+        })
+    }
+
+    /// write the table out if anything changed since the last save
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut table = self.table.write().expect("torrent table lock poisoned");
+        if table.dirty {
+            super::save_json(path, &table.torrents)?;
+            table.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// bring the table in line with the full list of torrents in qbittorrent: add the
+    /// new ones and forget the ones that were removed
+    pub fn sync<'a>(&self, torrents: impl Iterator<Item = (&'a str, &'a str)>) {
+        let mut table = self.table.write().expect("torrent table lock poisoned");
+        let mut present = HashSet::new();
+
+        for (hash, name) in torrents {
+            present.insert(hash);
+
+            if !table.torrents.contains_key(hash) {
+                table.torrents.insert(
+                    hash.to_string(),
+                    TorrentState {
+                        name: name.to_string(),
+                        first_seen: now(),
+                        paused: BTreeMap::new(),
+                        exempt: None,
+                    },
+                );
+                table.dirty = true;
+            }
+        }
+
+        let before = table.torrents.len();
+        table
+            .torrents
+            .retain(|hash, _| present.contains(hash.as_str()));
+        if table.torrents.len() != before {
+            println! {"forgot {} torrents removed from qbittorrent", before - table.torrents.len()};
+            table.dirty = true;
+        }
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .contains_key(hash)
+    }
+
+    /// whether we already paused the torrent for `reason`
+    pub fn paused_for(&self, hash: &str, reason: PauseReason) -> bool {
+        self.table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .get(hash)
+            .map(|x| x.paused.contains_key(&reason))
+            .unwrap_or(false)
+    }
+
+    pub fn mark_paused(&self, hash: &str, reason: PauseReason) {
+        let mut table = self.table.write().expect("torrent table lock poisoned");
+        if let Some(state) = table.torrents.get_mut(hash) {
+            state.paused.insert(reason, now());
+            table.dirty = true;
+        }
+    }
+
+    /// torrents we paused and that are still paused, most recent first
+    pub fn list_paused(&self) -> Vec<PausedTorrent> {
+        let mut torrents = self
+            .table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .iter()
+            .filter(|(_, state)| state.exempt.is_none())
+            .filter_map(|(hash, state)| {
+                let (reason, time) = state.paused.iter().max_by_key(|(_, time)| **time)?;
+                Some(PausedTorrent {
+                    hash: hash.clone(),
+                    name: state.name.clone(),
+                    reason: *reason,
+                    time: *time,
+                })
+            })
+            .collect::<Vec<_>>();
+        torrents.sort_by_key(|x| std::cmp::Reverse(x.time));
+        torrents
+    }
+
+    /// resume a torrent we paused. torrents paused for their title also lose the tag
+    /// marking them as banned
+    pub async fn undo(&self, qbit_api: &QbitApi, hash: &str) -> Result<PausedTorrent, Error> {
+        let torrent = self
+            .list_paused()
+            .into_iter()
+            .find(|x| x.hash == hash)
+            .ok_or_else(|| Error::NotFound(format! {"paused torrent {}", hash}))?;
+
+        if self.paused_for(hash, PauseReason::Title) {
+            qbit_api.remove_tags(hash, BANNED_TITLE_TAG).await?;
+        }
+        qbit_api.resume(hash).await?;
+
+        self.exempt(hash, ExemptReason::Undone)?;
+
+        println! {"resumed torrent paused by the monitor: {}", torrent.name};
+        Ok(torrent)
+    }
+
+    pub fn is_exempt(&self, hash: &str) -> bool {
+        self.table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .get(hash)
+            .map(|x| x.exempt.is_some())
+            .unwrap_or(false)
+    }
+
+    /// never touch the torrent again, until the exemption is cleared
+    pub fn exempt(&self, hash: &str, reason: ExemptReason) -> Result<(), Error> {
+        {
+            let mut table = self.table.write().expect("torrent table lock poisoned");
+            let state = table
+                .torrents
+                .get_mut(hash)
+                .ok_or_else(|| Error::NotFound(format! {"torrent {}", hash}))?;
+
+            state.exempt = Some(Exemption {
+                reason,
+                time: now(),
+            });
+            table.dirty = true;
+        }
+
+        self.save()
+    }
+
+    /// let the monitor check the torrent again, as if we had never paused it
+    pub fn clear_exemption(&self, hash: &str) -> Result<(), Error> {
+        {
+            let mut table = self.table.write().expect("torrent table lock poisoned");
+            let state = table
+                .torrents
+                .get_mut(hash)
+                .ok_or_else(|| Error::NotFound(format! {"torrent {}", hash}))?;
+
+            state.exempt = None;
+            state.paused.clear();
+            table.dirty = true;
+        }
+
+        self.save()
+    }
+
+    pub fn list_exempt(&self) -> Vec<ExemptTorrent> {
+        self.table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .iter()
+            .filter_map(|(hash, state)| {
+                let exempt = state.exempt?;
+                Some(ExemptTorrent {
+                    hash: hash.clone(),
+                    name: state.name.clone(),
+                    reason: exempt.reason,
+                    time: exempt.time,
+                })
+            })
+            .collect()
+    }
+
+    /// torrents we paused that are not exempt yet
+    pub(crate) fn paused_hashes(&self) -> Vec<String> {
+        self.table
+            .read()
+            .expect("torrent table lock poisoned")
+            .torrents
+            .iter()
+            .filter(|(_, state)| state.exempt.is_none() && !state.paused.is_empty())
+            .map(|(hash, _)| hash.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror::{MainData, Mirror};
+
+    // what the monitor does after every sync
+    fn sync(table: &TorrentTable, mirror: &Mirror) {
+        table.sync(
+            mirror
+                .torrents()
+                .map(|(hash, x)| (hash.as_str(), x.name.as_str())),
+        );
+    }
+
+    fn apply(mirror: &mut Mirror, json: &str) {
+        mirror.apply(serde_json::from_str::<MainData>(json).unwrap());
+    }
+
+    #[test]
+    fn sync_follows_the_mirror() {
+        let table = TorrentTable::load(None).unwrap();
+        let mut mirror = Mirror::new();
+
+        apply(
+            &mut mirror,
+            r#"{"rid": 1, "full_update": true,
+                "torrents": {"aaa": {"name": "Show"}, "bbb": {"name": "Movie"}}}"#,
+        );
+        sync(&table, &mirror);
+        assert!(table.contains("aaa") && table.contains("bbb"));
+
+        table.mark_paused("bbb", PauseReason::Tracker);
+        assert!(table.paused_for("bbb", PauseReason::Tracker));
+        assert!(!table.paused_for("bbb", PauseReason::Title));
+
+        // a rename does not reset what we know about the torrent
+        apply(
+            &mut mirror,
+            r#"{"rid": 2, "torrents": {"bbb": {"name": "Movie (2020)"}, "ccc": {"name": "New"}},
+                "torrents_removed": ["aaa"]}"#,
+        );
+        sync(&table, &mirror);
+        assert!(!table.contains("aaa"));
+        assert!(table.contains("ccc"));
+        assert!(table.paused_for("bbb", PauseReason::Tracker));
+        assert_eq!(table.paused_hashes(), ["bbb"]);
+        assert_eq!(table.list_paused()[0].name, "Movie");
+    }
+
+    #[test]
+    fn state_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format! {"autodl-torrents-{}", std::process::id()});
+        let path = dir.join("torrents.json");
+
+        let table = TorrentTable::load(Some(path.clone())).unwrap();
+        table.sync(vec![("aaa", "Show"), ("bbb", "Movie")].into_iter());
+        table.mark_paused("aaa", PauseReason::Title);
+        table.exempt("bbb", ExemptReason::Api).unwrap();
+        table.save().unwrap();
+
+        let loaded = TorrentTable::load(Some(path)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(loaded.paused_for("aaa", PauseReason::Title));
+        assert!(loaded.is_exempt("bbb"));
+        assert_eq!(loaded.list_exempt()[0].reason, ExemptReason::Api);
+        assert!(loaded.exempt("zzz", ExemptReason::Api).is_err());
+    }
+}
@@ -1,122 +1,505 @@
-use super::Error;
-
+use super::{Context, Error};
+
+use super::backoff::RetryConfig;
+use super::client::{ClientFactory, FeedHttp, FeedProxy, HttpConfig, ProxyConfig};
+use super::events::EventSender;
+use super::include;
+use super::metrics::Metrics;
+use super::migrate;
+use super::notify::{self, NotificationConfig};
+use super::ratelimit::{RateLimit, RateLimiter};
+use super::redact;
 use super::rss;
+use super::secret::Secret;
+use super::server::ServerConfig;
+use super::supervisor::Shutdown;
 
-use qbittorrent;
-
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use super::monitor::*;
 use reqwest;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use serde_yaml;
+use tokio::task::JoinHandle;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct FeedManager {
     feeds: Vec<RssFeed>,
 
+    /// matchers that feeds can refer to by name
+    #[serde(default)]
+    matchers: BTreeMap<String, TorrentMatch>,
+
     #[serde(rename = "qbittorrent")]
     qbit_data: QbittorrentAuthentication,
+
+    #[serde(default)]
+    retry: RetryConfig,
+
+    /// used by every feed that does not set its own `proxy`
+    proxy: Option<ProxyConfig>,
+
+    /// connection settings shared by all feeds
+    #[serde(default)]
+    client: HttpConfig,
+
+    /// requests allowed to each tracker host, shared by every feed and .torrent download
+    #[serde(default)]
+    rate_limits: HashMap<String, RateLimit>,
+    /// applied to hosts that are not in `rate_limits`
+    default_rate_limit: Option<RateLimit>,
+
+    /// seconds over which feed start times are spread out at launch
+    #[serde(default = "default_startup_stagger")]
+    startup_stagger: u64,
+
+    #[serde(default)]
+    notifications: NotificationConfig,
+
+    /// http server for metrics, not started when missing
+    server: Option<ServerConfig>,
+
+    /// directory the download history and torrent states are kept in across restarts
+    state_dir: Option<std::path::PathBuf>,
+
+    /// seconds to wait for running downloads to finish when stopping
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+
+    /// query parameters masked in all output, on top of passkey, authkey, torrent_pass
+    /// and apikey
+    #[serde(default)]
+    redact: Vec<String>,
+}
+
+fn default_startup_stagger() -> u64 {
+    30
+}
+fn default_shutdown_timeout() -> u64 {
+    30
 }
 impl FeedManager {
     // Fetch yaml of configs to download
     pub fn from_yaml(paths: &[&str]) -> Result<FeedManager, Error> {
-        // cycle through all the paths, figure if any of the file locations are valid
-        let mut iter = paths
-            .iter()
-            .map(|file_location| (file_location, std::fs::File::open(file_location)))
-            .filter(|(_file_loc, file_opening)| file_opening.is_ok())
-            .map(|(file_location, file)| (file_location, file.unwrap()));
-
-        let file = if let Some((filename, file)) = iter.next() {
-            println! {"FeedManager: using file from : {}", filename}
-            file
-        } else {
-            return Err(Error::ConfigMissing);
-        };
+        // cycle through all the paths, the first file that exists is read below
+        let filename =
+            if let Some(filename) = paths.iter().find(|x| std::path::Path::new(x).is_file()) {
+                println! {"FeedManager: using file from : {}", filename}
+                filename
+            } else {
+                return Err(Error::ConfigMissing(
+                    paths.iter().map(|x| x.to_string()).collect(),
+                ));
+            };
+
+        // included files are merged in, and references to environment variables and
+        // files filled in, before anything looks at the values
+        let mut value = include::load(std::path::Path::new(filename))?;
+        for change in migrate::canonicalize(&mut value) {
+            println! {"deprecated config: {}", change}
+        }
 
-        let mut yaml: FeedManager = serde_yaml::from_reader(file)?;
+        let mut yaml: FeedManager = serde_yaml::from_value(value).config_context(*filename)?;
+        yaml.resolve_names().config_context(*filename)?;
         yaml.lowercase();
+        redact::add_params(&yaml.redact);
+
+        for feed in &yaml.feeds {
+            feed.http
+                .check()
+                .feed_context(&feed.url)
+                .config_context(*filename)?;
+        }
 
         Ok(yaml)
     }
 
+    /// json schema of the config, the same for every format it can be written in
+    pub fn schema() -> String {
+        let mut schema =
+            serde_json::to_value(schemars::schema_for!(FeedManager)).expect("schema serializes");
+
+        // `include` is taken out before the config is deserialized, so it is not a field
+        schema["properties"]["include"] = serde_json::json!({
+            "anyOf": [
+                {"type": "string"},
+                {"type": "array", "items": {"type": "string"}}
+            ]
+        });
+
+        serde_json::to_string_pretty(&schema).expect("schema serializes")
+    }
+
+    /// one line description of what was loaded
+    pub fn summary(&self) -> String {
+        format! {
+            "{} feeds, {} named matchers",
+            self.feeds.len(),
+            self.matchers.len()
+        }
+    }
+
     fn lowercase(&mut self) {
         for i in &mut self.feeds {
             i.lowercase()
         }
     }
-    pub async fn qbit(&self) -> Result<QbitMonitor, Error> {
-        let qbit = QbitMonitor::new(self.qbit_data.clone()).await?;
+
+    // give every feed and matcher a name, and put named matchers into the feeds
+    // referring to them
+    fn resolve_names(&mut self) -> Result<(), Error> {
+        for (name, matcher) in &mut self.matchers {
+            matcher.name = name.clone();
+        }
+
+        // names given in the config have to be unique, generated ones are made unique
+        let mut taken = HashSet::new();
+        for feed in self.feeds.iter().filter(|x| !x.name.is_empty()) {
+            if !taken.insert(feed.name.clone()) {
+                return Err(Error::DuplicateName {
+                    kind: "feed",
+                    name: feed.name.clone(),
+                });
+            }
+        }
+
+        // the same goes for matchers, where a named one used by several feeds is still
+        // the one matcher
+        let mut matcher_names = self.matchers.keys().cloned().collect::<HashSet<_>>();
+        let inline_names = self
+            .feeds
+            .iter()
+            .flat_map(|x| x.matcher_refs.iter())
+            .filter_map(|x| match x {
+                MatcherRef::Inline(matcher) if !matcher.name.is_empty() => Some(&matcher.name),
+                _ => None,
+            });
+        for name in inline_names {
+            if !matcher_names.insert(name.clone()) {
+                return Err(Error::DuplicateName {
+                    kind: "matcher",
+                    name: name.clone(),
+                });
+            }
+        }
+
+        for feed in &mut self.feeds {
+            if feed.name.is_empty() {
+                feed.name = unique_name(feed.default_name(), &mut taken);
+            }
+
+            let matchers = &self.matchers;
+            feed.matcher = std::mem::take(&mut feed.matcher_refs)
+                .into_iter()
+                .enumerate()
+                .map(|(index, x)| match x {
+                    MatcherRef::Inline(mut matcher) => {
+                        matcher.fill_name(index, &mut matcher_names);
+                        Ok(matcher)
+                    }
+                    MatcherRef::Named(name) => {
+                        matchers
+                            .get(&name)
+                            .cloned()
+                            .ok_or_else(|| Error::UnknownMatcher {
+                                feed: feed.name.clone(),
+                                name,
+                            })
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn qbit(
+        &self,
+        events: &EventSender,
+        metrics: &Arc<Metrics>,
+    ) -> Result<QbitMonitor, Error> {
+        let state_path = self.state_dir.as_ref().map(|x| x.join("torrents.json"));
+        let qbit = QbitMonitor::new(
+            self.qbit_data.clone(),
+            events.clone(),
+            Arc::clone(metrics),
+            state_path,
+        )
+        .await?;
         Ok(qbit)
     }
 
-    pub fn split(self, qbit: &Arc<qbittorrent::Api>) -> Vec<FeedMonitor> {
+    pub fn server(&self) -> Option<&ServerConfig> {
+        self.server.as_ref()
+    }
+
+    /// names of every matcher in the config, for keeping the names of matchers added
+    /// through the api unique
+    pub fn matcher_names(&self) -> MatcherNames {
+        let named = self.matchers.keys().cloned().collect();
+        let taken = self
+            .feeds
+            .iter()
+            .flat_map(|x| x.matcher.iter())
+            .map(|x| x.name.clone())
+            .collect();
+        MatcherNames::new(named, taken)
+    }
+
+    /// folders the api may save into: `save_roots` of the server, or else the save
+    /// folder of every matcher in the config
+    pub fn save_roots(&self) -> Vec<std::path::PathBuf> {
+        if let Some(server) = self.server.as_ref().filter(|x| !x.save_roots.is_empty()) {
+            return server.save_roots.clone();
+        }
+
+        let mut roots = self
+            .feeds
+            .iter()
+            .flat_map(|x| x.matcher.iter())
+            .map(|x| std::path::PathBuf::from(&x.save_folder))
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots.dedup();
+        roots
+    }
+
+    pub fn history_path(&self) -> Option<std::path::PathBuf> {
+        self.state_dir.as_ref().map(|x| x.join("history.json"))
+    }
+
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout)
+    }
+
+    /// start forwarding events to the configured notification sinks
+    pub fn start_notifications(
+        &self,
+        events: &EventSender,
+        shutdown: &Shutdown,
+    ) -> Vec<JoinHandle<()>> {
+        notify::spawn(&self.notifications, events, shutdown)
+    }
+
+    /// a client factory built from the `client` section of the config
+    pub fn client_factory(&self) -> ClientFactory {
+        ClientFactory::new(self.client.clone())
+    }
+
+    /// a rate limiter built from the `rate_limits` section of the config
+    pub fn rate_limiter(&self) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(
+            self.rate_limits.clone(),
+            self.default_rate_limit.clone(),
+        ))
+    }
+
+    pub fn split(
+        self,
+        handles: &FeedHandles,
+        clients: &ClientFactory,
+    ) -> Result<Vec<FeedMonitor>, Error> {
+        let retry = self.retry;
+        let proxy = self.proxy;
+        let count = self.feeds.len() as u64;
+        let stagger = self.startup_stagger;
         self.feeds
             .into_iter()
-            .map(|x| FeedMonitor::from_feed(x, Arc::clone(qbit)))
+            .enumerate()
+            .map(|(index, x)| {
+                let retry = x.retry.clone().unwrap_or_else(|| retry.clone());
+                let proxy = FeedProxy::or(x.proxy.as_ref(), proxy.as_ref());
+                let client = clients.client(proxy).feed_context(&x.url)?;
+                handles.status.register_feed(&x.name);
+
+                // qbittorrent fetches .torrent links itself, without the proxy or any
+                // of the feed's credentials, so those are downloaded by us instead
+                let fetch_torrents = proxy.is_some() || x.http.has_credentials();
+
+                // spread the first polls evenly so feeds do not all fire at launch
+                let start_delay =
+                    std::time::Duration::from_millis(stagger * 1000 * index as u64 / count);
+
+                Ok(
+                    FeedMonitor::from_feed(x, client, fetch_torrents, retry, handles.clone())
+                        .with_start_delay(start_delay),
+                )
+            })
             .collect()
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
 pub struct QbittorrentAuthentication {
     pub(crate) username: String,
-    pub(crate) password: String,
+    pub(crate) password: Secret,
     pub(crate) address: String,
     pub(crate) trackers: Vec<String>,
     pub(crate) title_bans: Option<Vec<String>>,
     pub(crate) file_bans: Option<Vec<String>>,
+    /// torrents in this category are never paused
+    #[serde(default = "default_exempt_category")]
+    pub(crate) exempt_category: String,
+    /// torrents with this tag are never paused
+    #[serde(default = "default_exempt_tag")]
+    pub(crate) exempt_tag: String,
+}
+
+fn default_exempt_category() -> String {
+    "AUTODL_EXEMPT".to_string()
+}
+fn default_exempt_tag() -> String {
+    "autodl:exempt".to_string()
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct RssFeed {
+    /// how the feed is shown in logs, metrics and tags. defaults to the host of `url`
+    #[serde(default)]
+    pub name: String,
     pub url: String,
     pub update_interval: u32,
     #[serde(default)]
     pub last_announce: u32,
+    /// written out in place, or the name of one of the top level `matchers`
+    #[serde(rename = "matcher")]
+    matcher_refs: Vec<MatcherRef>,
+    #[serde(skip)]
     pub matcher: Vec<TorrentMatch>,
+    /// overrides the top level `retry` settings for this feed
+    pub retry: Option<RetryConfig>,
+    #[serde(default)]
+    pub http: FeedHttp,
+    /// overrides the top level `proxy` for this feed, `none` connects directly
+    pub proxy: Option<FeedProxy>,
+}
+
+// feed urls usually carry a passkey
+impl std::fmt::Debug for RssFeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RssFeed")
+            .field("name", &self.name)
+            .field("url", &redact::url(&self.url))
+            .field("update_interval", &self.update_interval)
+            .field("last_announce", &self.last_announce)
+            .field("matcher", &self.matcher)
+            .field("retry", &self.retry)
+            .field("http", &self.http)
+            .field("proxy", &self.proxy)
+            .finish()
+    }
+}
+
+#[derive(Debug, JsonSchema)]
+#[schemars(untagged)]
+enum MatcherRef {
+    Named(String),
+    Inline(TorrentMatch),
+}
+
+// written out by hand so that a mistake in an inline matcher reports what is wrong
+// with it, instead of only that nothing matched
+impl<'de> Deserialize<'de> for MatcherRef {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::String(name) => Ok(MatcherRef::Named(name)),
+            value => TorrentMatch::deserialize(value)
+                .map(MatcherRef::Inline)
+                .map_err(D::Error::custom),
+        }
+    }
 }
+
 impl RssFeed {
+    // the host of the url, so that a passkey in it never ends up in a name
+    fn default_name(&self) -> String {
+        reqwest::Url::parse(&self.url)
+            .ok()
+            .and_then(|x| x.host_str().map(|x| x.to_string()))
+            .unwrap_or_else(|| "feed".to_string())
+    }
+
     pub async fn fetch_new(
         &self,
         pool: &reqwest::Client,
-    ) -> Result<Vec<rss::TorrentData<'_>>, Error> {
-        let mut header = reqwest::header::HeaderMap::with_capacity(1);
-        header.insert(
-            "User-Agent",
-            "Mozilla/5.0 (Windows NT 6.1; WOW64; Trident/7.0; rv:11.0) like Gecko".parse()?,
-        );
-
-        let response: &[u8] = &pool
-            .get(&self.url)
+        cache: &rss::FeedCache,
+    ) -> Result<rss::Fetched<'_>, Error> {
+        let mut header = reqwest::header::HeaderMap::with_capacity(2);
+        cache.apply(&mut header)?;
+
+        let response = self
+            .http
+            .request(pool, &self.url)
+            .feed_context(&self.url)?
             .headers(header)
             .send()
-            .await?
-            .bytes()
-            .await?;
+            .await
+            .feed_context(&self.url)?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|x| x.to_str().ok())
+            .and_then(parse_retry_after);
+
+        // a 503 without `Retry-After` is just the tracker being down, which is
+        // reported with the rest of the bad statuses below
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (status == reqwest::StatusCode::SERVICE_UNAVAILABLE && retry_after.is_some())
+        {
+            return Err(Error::RateLimited {
+                status: status.as_u16(),
+                retry_after,
+            }
+            .feed(&self.url));
+        }
+
+        // nothing has changed since the last time we looked
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(rss::Fetched::default());
+        }
 
-        let data = rss::xml_to_torrents(response)?;
+        let validators = response.headers().clone();
+        let final_url = response.url().to_string();
+        let content_type = validators
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string());
+        let response: &[u8] = &response.bytes().await.feed_context(&self.url)?;
 
+        rss::check_response(status, &final_url, content_type.as_deref(), response)
+            .feed_context(&self.url)?;
+
+        let data = rss::xml_to_torrents(response).feed_context(&self.url)?;
+
+        // only remember the validators once the body has parsed, otherwise a broken
+        // response could be cached and never fetched again
+        let mut cache = rss::FeedCache::default();
+        cache.update(&validators);
+
+        let parsed = data.len();
         let filter_data = data
-            .into_iter()
+            .iter()
             .filter_map(|x| {
                 // make sure that the file matches at least one type condition
-                let mut data = None;
-
-                for mat in self.matcher.iter() {
-                    if mat.match_title(&x.title) && mat.match_tags(&x.tags) {
-                        data = Some(rss::TorrentData::from_serde_data(x, mat));
-                        break;
-                    }
-                }
-
-                data
+                self.matcher
+                    .iter()
+                    .find(|mat| mat.matches(x))
+                    .map(|mat| rss::TorrentData::from_serde_data(x.clone(), mat))
             })
             .collect::<Vec<_>>();
 
-        Ok(filter_data)
+        Ok(rss::Fetched {
+            parsed,
+            matched: filter_data,
+            items: Some(data),
+            cache: Some(cache),
+        })
     }
 
     fn lowercase(&mut self) {
@@ -126,9 +509,40 @@ impl RssFeed {
     }
 }
 
+// `base`, or `base-2`, `base-3` and so on when that is taken already. the name
+// returned is taken from then on
+fn unique_name(base: String, taken: &mut HashSet<String>) -> String {
+    let mut name = base.clone();
+    let mut count = 1;
+    while taken.contains(&name) {
+        count += 1;
+        name = format! {"{}-{}", base, count};
+    }
+
+    taken.insert(name.clone());
+    name
+}
+
+// `Retry-After` is either a number of seconds or an http date
+fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
 type Matcher = Option<Vec<Vec<String>>>;
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Serialize, Debug, Clone)]
 pub struct TorrentMatch {
+    /// how the matcher is shown in logs, metrics and tags. defaults to the last folder
+    /// of `save_folder`
+    #[serde(default)]
+    pub name: String,
     pub title_wanted: Matcher,
     pub title_banned: Matcher,
 
@@ -136,9 +550,11 @@ pub struct TorrentMatch {
     pub tags_banned: Matcher,
     pub save_folder: String,
     pub start_paused: Option<bool>,
+    /// qbittorrent category for the torrents this matcher downloads
+    pub category: Option<String>,
 }
 impl TorrentMatch {
-    fn lowercase(&mut self) {
+    pub(crate) fn lowercase(&mut self) {
         let lower = |arg: &Matcher| match &arg {
             Some(values) => {
                 let vals: Vec<Vec<String>> = values
@@ -156,6 +572,35 @@ impl TorrentMatch {
         self.tags_wanted = lower(&self.tags_wanted);
     }
 
+    /// name the matcher after its save folder if it has no name, or after its position
+    /// in the feed if that does not work either. a number is appended when the name is
+    /// in `taken`, and the name used is added to it
+    pub(crate) fn fill_name(&mut self, index: usize, taken: &mut HashSet<String>) {
+        if !self.name.is_empty() {
+            return;
+        }
+
+        let base = self
+            .save_folder
+            .trim_end_matches(['/', '\\'])
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        let base = if base.is_empty() {
+            format! {"matcher-{}", index}
+        } else {
+            base
+        };
+        self.name = unique_name(base, taken);
+    }
+
+    /// whether an rss item is accepted by this matcher
+    pub(crate) fn matches(&self, item: &rss::SerdeTorrentData) -> bool {
+        self.match_title(&item.title) && self.match_tags(&item.tags)
+    }
+
     fn match_title(&self, title_input: &String) -> bool {
         let mut good_title = true;
 
@@ -210,17 +655,6 @@ impl TorrentMatch {
 
         good_tags
     }
-
-    pub(crate) fn start_condition(&self) -> String {
-        let x = match self.start_paused {
-            Some(val) => match val {
-                true => "true",
-                false => "false",
-            },
-            None => "false",
-        };
-        x.into()
-    }
 }
 
 trait Contains_ {
@@ -256,3 +690,143 @@ impl Contains_ for String {
         good
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn committed_schema_is_current() {
+        // regenerate with `autodl-rss schema > config.schema.json`
+        let committed = include_str!("../config.schema.json");
+        assert_eq!(committed.trim_end(), FeedManager::schema());
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_after_as_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(600));
+        let wait = parse_retry_after(&date).unwrap();
+        assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+    }
+
+    #[test]
+    fn retry_after_in_the_past() {
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn retry_after_garbage() {
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+    }
+
+    const QBITTORRENT: &str = "
+qbittorrent:
+  username: admin
+  password: adminadmin
+  address: http://localhost:8080
+  trackers: []
+";
+
+    fn resolved(feeds: &str) -> Result<FeedManager, Error> {
+        let mut config: FeedManager = serde_yaml::from_str(&format! {"{}{}", QBITTORRENT, feeds})?;
+        config.resolve_names()?;
+        Ok(config)
+    }
+
+    fn matcher_names(config: &FeedManager) -> Vec<Vec<&str>> {
+        config
+            .feeds
+            .iter()
+            .map(|x| x.matcher.iter().map(|x| x.name.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn generated_matcher_names_are_unique() {
+        let config = resolved(
+            "
+matchers:
+  shows: {save_folder: /downloads/tv}
+feeds:
+  - url: https://a.example/rss
+    update_interval: 60
+    matcher:
+      - shows
+      - save_folder: /downloads/shows
+      - save_folder: /other/shows
+  - url: https://b.example/rss
+    update_interval: 60
+    matcher:
+      - shows
+      - save_folder: /downloads/shows/
+      - save_folder: ''
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            matcher_names(&config),
+            [
+                vec!["shows", "shows-2", "shows-3"],
+                vec!["shows", "shows-4", "matcher-2"]
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_matcher_names_must_be_unique() {
+        let duplicate = |feeds: &str| match resolved(feeds) {
+            Err(Error::DuplicateName { kind, name }) => (kind, name),
+            x => panic!("expected a duplicate name, got {:?}", x.map(|_| ())),
+        };
+
+        let inline_twice = "
+feeds:
+  - url: https://a.example/rss
+    update_interval: 60
+    matcher: [{name: tv, save_folder: /a}]
+  - url: https://b.example/rss
+    update_interval: 60
+    matcher: [{name: tv, save_folder: /b}]
+";
+        assert_eq!(duplicate(inline_twice), ("matcher", "tv".to_string()));
+
+        let shadows_named = "
+matchers:
+  tv: {save_folder: /a}
+feeds:
+  - url: https://a.example/rss
+    update_interval: 60
+    matcher: [tv, {name: tv, save_folder: /b}]
+";
+        assert_eq!(duplicate(shadows_named), ("matcher", "tv".to_string()));
+    }
+
+    #[test]
+    fn inline_matcher_errors_are_kept() {
+        let error = resolved(
+            "
+feeds:
+  - url: https://a.example/rss
+    update_interval: 60
+    matcher: [{save_folder: /a, start_paused: maybe}]
+",
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("expected a boolean"), "{}", error);
+        assert!(!error.contains("did not match any variant"), "{}", error);
+    }
+}